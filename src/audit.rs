@@ -0,0 +1,117 @@
+// =========================================================================
+// --- Audit Records ---
+// =========================================================================
+
+// A compact, serializable record of an error for the audit trail (see
+// `AppError::to_audit_record`). It's meant for the audit log file, never
+// for the client: `detail` is the internal detail, though already redacted
+// the same way as in the server log.
+//
+// `AuditLog` keeps the most recent records in memory for the development
+// endpoint `GET /debug/last-errors`, which only answers with the
+// configured `DEBUG_TOKEN`.
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AppConfig;
+use crate::redaction::{redact_secrets, sanitize_detail_for_log};
+use crate::{AppError, require_bearer};
+
+// How many records `AuditLog` keeps; older ones are dropped.
+const AUDIT_LOG_CAPACITY: usize = 100;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    // The internal error code, e.g. `db_error` or `transient_db_error`.
+    pub variant: &'static str,
+    pub status: u16,
+    // Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub request_id: String,
+    pub detail: String,
+}
+
+// The current time as seconds since the Unix epoch.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// The most recent audit records, shared by all workers via `web::Data`.
+#[derive(Default)]
+pub struct AuditLog {
+    records: Mutex<VecDeque<AuditRecord>>,
+}
+
+impl AuditLog {
+    pub fn push(&self, record: AuditRecord) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() >= AUDIT_LOG_CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    // A copy of the records, oldest first.
+    fn snapshot(&self) -> Vec<AuditRecord> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.iter().cloned().collect()
+    }
+}
+
+// GET /debug/last-errors: the recent audit records as JSON, oldest first.
+// Requires `Authorization: Bearer <DEBUG_TOKEN>`; with no token configured
+// nobody gets in. Even then the details are redacted and cut once more, in
+// case a record was made before redaction learned a new secret pattern.
+pub async fn last_errors_endpoint(
+    req: HttpRequest,
+    config: web::Data<AppConfig>,
+    audit: web::Data<AuditLog>,
+) -> Result<HttpResponse, AppError> {
+    let expected = config
+        .debug_token
+        .as_ref()
+        .ok_or_else(|| AppError::Unauthorized("DEBUG_TOKEN is not configured".to_string()))?;
+    require_bearer(&req, expected.expose_secret(), "DEBUG_TOKEN")?;
+
+    let records: Vec<AuditRecord> = audit
+        .snapshot()
+        .into_iter()
+        .map(|record| AuditRecord {
+            detail: sanitize_detail_for_log(
+                &redact_secrets(&record.detail),
+                config.max_log_detail_len,
+            ),
+            ..record
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(records))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::AppError;
+
+    // A DB error's record serializes with its code, status and request id,
+    // and a detail that's already redacted like the log line.
+    #[test]
+    fn a_db_error_record_has_its_detail_redacted() {
+        let e = AppError::internal(
+            "connect failed: DB_CONNECTION_STRING=postgres://admin:supersecret@db:5432/app",
+        );
+        let record = serde_json::to_value(e.to_audit_record("req-7")).unwrap();
+        assert_eq!(record["variant"], "db_error");
+        assert_eq!(record["status"], 500);
+        assert_eq!(record["request_id"], "req-7");
+        assert!(record["timestamp"].as_u64().unwrap() > 0);
+        let detail = record["detail"].as_str().unwrap();
+        assert!(detail.starts_with("connect failed: "), "{}", detail);
+        assert!(!detail.contains("supersecret"), "{}", detail);
+    }
+}
@@ -0,0 +1,61 @@
+// =========================================================================
+// --- In-Memory Product Catalog ---
+// =========================================================================
+
+// A tiny product store shared by all workers via `web::Data`, so the demo
+// has something to create. Names are compared case-insensitively.
+
+use actix_web::{HttpResponse, web};
+use log::info;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::redaction::sanitize_for_log;
+use crate::{AppError, FieldError};
+
+#[derive(Default)]
+pub struct Catalog {
+    names: Mutex<HashSet<String>>,
+}
+
+impl Catalog {
+    // Add `name`, or return `AppError::Conflict` if it's already there.
+    // The conflicting key goes in the detail, which only reaches the log.
+    pub fn insert(&self, name: &str) -> Result<(), AppError> {
+        let key = name.trim().to_lowercase();
+        let mut names = self.names.lock().unwrap_or_else(|e| e.into_inner());
+        if !names.insert(key.clone()) {
+            return Err(AppError::Conflict(format!(
+                "unique constraint 'products_name_key' violated by name '{}'",
+                key
+            )));
+        }
+        Ok(())
+    }
+}
+
+// The JSON body accepted by `create_product`.
+#[derive(Deserialize)]
+pub struct NewProduct {
+    name: String,
+}
+
+// POST /products with `{"name":"..."}`. Returns 201 on success and 409
+// (with the generic conflict message) if the product already exists.
+pub async fn create_product(
+    catalog: web::Data<Catalog>,
+    body: web::Json<NewProduct>,
+) -> Result<HttpResponse, AppError> {
+    info!(
+        "Received create product request for: {}",
+        sanitize_for_log(&body.name)
+    );
+    if body.name.trim().is_empty() {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "name", "required",
+        )]));
+    }
+    catalog.insert(&body.name)?;
+    Ok(HttpResponse::Created().finish())
+}
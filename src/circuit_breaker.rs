@@ -0,0 +1,196 @@
+// =========================================================================
+// --- Database Circuit Breaker ---
+// =========================================================================
+
+// Stops us from hammering a database that keeps failing. After
+// `failure_threshold` failures in a row the breaker opens, and queries
+// fail fast with `AppError::ServiceUnavailable` (503) without touching the
+// database. Once `cooldown` has passed, a single probe query is let
+// through (half-open): if it succeeds the breaker closes again, otherwise
+// it reopens for another cooldown. Shared by all workers via `web::Data`.
+
+use log::{info, warn};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{AppError, DbErrorKind};
+
+#[derive(Debug)]
+enum State {
+    // Queries run normally; counts consecutive failures.
+    Closed { failures: u32 },
+    // Queries are refused until `until`.
+    Open { until: Instant },
+    // One probe query has been running since `since`; everyone else is
+    // still refused. A probe that never reports back (its request was
+    // cancelled) is replaced after another cooldown.
+    HalfOpen { since: Instant },
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    // Run `query` unless the breaker is open, and record how it went.
+    // Only transient database failures and timeouts count against the
+    // database: a permanent error (bad SQL, a violated constraint) is the
+    // query's fault, not a sign the database is down.
+    pub async fn call<T>(
+        &self,
+        query: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        self.before_call()?;
+        let result = query.await;
+        let failed = matches!(
+            result,
+            Err(AppError::DbError {
+                kind: DbErrorKind::Transient,
+                ..
+            } | AppError::Timeout(_))
+        );
+        self.record(failed);
+        result
+    }
+
+    // Refuse the call while open, or while a half-open probe is running.
+    fn before_call(&self) -> Result<(), AppError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now >= until => {
+                info!("Database circuit breaker half-open: sending a probe query");
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            State::HalfOpen { since } if now >= since + self.cooldown => {
+                info!("Database circuit breaker probe went missing: sending another");
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            State::Open { until } => Err(AppError::ServiceUnavailable {
+                // Kept free of the remaining time, so repeats share a fingerprint.
+                detail: format!(
+                    "database circuit breaker open (cooldown {:?})",
+                    self.cooldown
+                ),
+                retry_after_secs: (until - now).as_secs().max(1),
+            }),
+            State::HalfOpen { .. } => Err(AppError::ServiceUnavailable {
+                detail: "database circuit breaker half-open, probe in flight".to_string(),
+                retry_after_secs: 1,
+            }),
+        }
+    }
+
+    fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = match (&*state, failed) {
+            (State::HalfOpen { .. }, false) => {
+                info!("Database circuit breaker closed: probe query succeeded");
+                State::Closed { failures: 0 }
+            }
+            (State::HalfOpen { .. }, true) => {
+                warn!(
+                    "Database circuit breaker reopened for {:?}: probe query failed",
+                    self.cooldown
+                );
+                State::Open {
+                    until: Instant::now() + self.cooldown,
+                }
+            }
+            (State::Closed { failures }, true) if failures + 1 >= self.failure_threshold => {
+                warn!(
+                    "Database circuit breaker opened for {:?} after {} consecutive failures",
+                    self.cooldown,
+                    failures + 1
+                );
+                State::Open {
+                    until: Instant::now() + self.cooldown,
+                }
+            }
+            (State::Closed { failures }, true) => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            // Calls that started before the breaker opened; the open state
+            // (and its cooldown) stays as it is.
+            (State::Open { until }, _) => State::Open { until: *until },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn transient() -> Result<(), AppError> {
+        Err(AppError::transient_db("connection reset by peer"))
+    }
+
+    #[actix_web::test]
+    async fn repeated_failures_open_the_breaker() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        for _ in 0..3 {
+            let result = breaker.call(async { transient() }).await;
+            assert!(matches!(result, Err(AppError::DbError { .. })));
+        }
+
+        // Open: the query isn't run at all
+        let calls = AtomicU32::new(0);
+        let result = breaker
+            .call(async {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .await;
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+        match result {
+            Err(AppError::ServiceUnavailable {
+                retry_after_secs, ..
+            }) => assert!((1..=60).contains(&retry_after_secs)),
+            other => panic!("expected ServiceUnavailable, got {:?}", other),
+        }
+    }
+
+    #[actix_web::test]
+    async fn permanent_failures_and_successes_keep_it_closed() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        for _ in 0..3 {
+            let permanent = breaker
+                .call(async { Err::<(), _>(AppError::internal("syntax error")) })
+                .await;
+            assert!(!matches!(
+                permanent,
+                Err(AppError::ServiceUnavailable { .. })
+            ));
+        }
+        // A success resets the count of consecutive failures
+        let _ = breaker.call(async { transient() }).await;
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+        let _ = breaker.call(async { transient() }).await;
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn a_successful_probe_closes_it_again() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        let _ = breaker.call(async { transient() }).await;
+        // The cooldown has passed, so this is the half-open probe
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+        assert!(breaker.call(async { Ok(()) }).await.is_ok());
+    }
+}
@@ -0,0 +1,596 @@
+// =========================================================================
+// --- Application Configuration ---
+// =========================================================================
+
+// Settings shared with handlers via `web::Data<AppConfig>`.
+// Values are read once at startup, falling back to safe defaults.
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use log::warn;
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::redaction::{DEFAULT_BASE64_MIN_LEN, RedactionOptions, SensitiveString};
+use crate::{AppError, require_admin};
+
+// The HTML error page. `{message}` is replaced with the generic
+// (never detailed) message and `{reference}` with the correlation id.
+pub const DEFAULT_ERROR_PAGE: &str = "<h1>Error!</h1><p>{message}</p><p>Reference: {reference}</p>";
+
+// Validation codes the crate itself uses, all safe to send to clients.
+pub const DEFAULT_SAFE_VALIDATION_CODES: &[&str] = &[
+    "required",
+    "too_long",
+    "too_many",
+    "too_broad",
+    "too_complex",
+    "invalid_characters",
+    "invalid_value",
+    "malformed",
+    "truncated",
+    "unknown_parameter",
+    "invalid",
+];
+
+// How many bytes of an internal error detail are logged by default.
+pub const DEFAULT_MAX_LOG_DETAIL_LEN: usize = 256;
+
+// How error responses pick their format (`ERROR_FORMAT`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormatMode {
+    // Negotiate from the `Accept` header (the default).
+    Auto,
+    // Always problem+json: for pure APIs that must never answer with HTML.
+    Json,
+    // Always the HTML error page, except on routes forced to JSON.
+    Html,
+}
+
+impl ErrorFormatMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(ErrorFormatMode::Auto),
+            "json" => Some(ErrorFormatMode::Json),
+            "html" => Some(ErrorFormatMode::Html),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorFormatMode::Auto => "auto",
+            ErrorFormatMode::Json => "json",
+            ErrorFormatMode::Html => "html",
+        }
+    }
+}
+
+pub struct AppConfig {
+    // How long a database query may run before we give up with a 504.
+    pub query_timeout: Duration,
+    // Dev mode: include internal error details in responses, for the
+    // variants `AppError::is_client_safe` allows. Never enable this in production.
+    pub expose_errors: bool,
+    // Rate limit for the search endpoints, per client IP: a burst of
+    // `rate_limit_burst` requests, refilled at `rate_limit_per_sec`.
+    pub rate_limit_burst: u32,
+    pub rate_limit_per_sec: f64,
+    // Largest request body (in bytes) accepted by the JSON endpoints.
+    // Anything bigger is rejected with a 413 before it reaches query code.
+    pub max_body_bytes: usize,
+    // On SIGTERM/ctrl-c, how long in-flight requests may keep running
+    // before the workers are stopped anyway.
+    pub shutdown_timeout: Duration,
+    // Template for HTML error pages, see `DEFAULT_ERROR_PAGE`. Operators can
+    // brand it; only the two placeholders are ever filled in.
+    pub error_page_template: String,
+    // Search results with more rows than this are streamed in chunks
+    // instead of being rendered into one response body.
+    pub stream_threshold: usize,
+    // Product categories a search may be filtered by (lowercase). Any other
+    // category is rejected with a 403.
+    pub allowed_categories: HashSet<String>,
+    // Database circuit breaker: open after this many consecutive failures,
+    // and refuse queries for `breaker_cooldown` before probing again.
+    pub breaker_failure_threshold: u32,
+    pub breaker_cooldown: Duration,
+    // Request headers (lowercase names) whose values `log_request` replaces
+    // with `***`, because they carry credentials.
+    pub redacted_headers: HashSet<String>,
+    // The body and content type answered for unknown paths. It's a fixed
+    // string: the requested path is never reflected into it.
+    pub not_found_body: String,
+    pub not_found_content_type: String,
+    // Production behind a TLS-terminating proxy: redirect plain HTTP
+    // requests (as reported by `X-Forwarded-Proto`/`Forwarded`) to HTTPS
+    // and send `Strict-Transport-Security`. Off by default for local dev.
+    pub enforce_https: bool,
+    // The host HTTPS redirects point at. The client's `Host` is never used
+    // for them, since anyone can set it; with no canonical host, plain HTTP
+    // requests are refused instead of redirected.
+    pub canonical_host: Option<String>,
+    // Peers whose `Forwarded`/`X-Forwarded-Proto` headers are believed.
+    // From anyone else, only the connection itself says whether it's HTTPS.
+    pub trusted_proxies: Vec<IpAddr>,
+    // The most bytes of an internal error detail written to the log; longer
+    // details are cut and marked `…(truncated)`. Keeps huge details (and
+    // any secrets buried in them) out of the log in bulk.
+    pub max_log_detail_len: usize,
+    // Maintenance mode: answer every request (except health checks) with a
+    // 503 and `Retry-After`, so operators can take the service down cleanly.
+    pub maintenance_mode: bool,
+    // The most search results returned per page; larger `per_page` values
+    // are clamped to this. Also the page size when none is given.
+    pub max_per_page: usize,
+    // Exact strings the `SecretScrubber` must never mask, such as product
+    // SKUs that look like access keys.
+    pub scrubber_allowlist: Vec<String>,
+    // Requests whose handler takes longer than this are logged as slow
+    // (a warning only; `query_timeout` is what actually stops queries).
+    pub slow_request_threshold: Duration,
+    // The database connection string. It usually embeds a password, so it's
+    // a `SensitiveString` and `to_safe_json` only shows its scheme.
+    pub database_url: Option<SensitiveString>,
+    // Overrides content negotiation for error responses, see `ErrorFormatMode`.
+    pub error_format: ErrorFormatMode,
+    // How many failed requests (redacted query and body) to keep for
+    // `/admin/failed-requests`. 0, the default, turns capturing off.
+    pub failure_capture_size: usize,
+    // The longest request URI (path plus query string, in bytes) we accept;
+    // longer ones get a 414 before they are logged or parsed.
+    pub max_uri_len: usize,
+    // Validation codes that may reach the client; any other code is sent as
+    // `invalid`, in case someone put free-form (possibly sensitive) text
+    // into a `FieldError`. The real code is still logged.
+    pub safe_validation_codes: HashSet<String>,
+    // Send a `Server-Timing` header with phase durations on searches.
+    // Off by default, since timings leak a little about what the server did.
+    pub server_timing: bool,
+    // Per-client error budget: a client IP causing `error_budget_max`
+    // 404/400 errors within `error_budget_window` is logged as a likely
+    // scanner (0 turns this off). With `error_budget_block`, it also gets
+    // 429s until the window has room again.
+    pub error_budget_max: usize,
+    pub error_budget_window: Duration,
+    pub error_budget_block: bool,
+    // Bearer token for `GET /debug/last-errors`. `None`, the default, keeps
+    // the endpoint closed.
+    pub debug_token: Option<SensitiveString>,
+    // Bearer token for the admin endpoints (`/secure-admin`,
+    // `/admin/failed-requests`, `/config`). `None`, the default, keeps them
+    // closed.
+    pub admin_token: Option<SensitiveString>,
+    // The shortest run of base64 that `redact_secrets` masks as a likely
+    // encoded secret (0 turns this off). Lower it to catch shorter tokens,
+    // at the price of masking more harmless identifiers.
+    pub base64_min_len: usize,
+    // Paths answering with `Deprecation: true` (and `Sunset`, if set), on
+    // errors as well as successes. A path also covers everything below it,
+    // e.g. `/secure-search` covers `/secure-search/batch`.
+    pub deprecated_paths: Vec<String>,
+    // When deprecated paths go away, as an HTTP date
+    // (`Sat, 01 Nov 2025 00:00:00 GMT`).
+    pub sunset: Option<String>,
+    // Reject searches whose query string has parameters beyond `product`,
+    // `category`, `page` and `per_page`, against parameter pollution.
+    pub strict_params: bool,
+    // How many worker threads serve requests (by default, one per CPU), and
+    // how long an idle keep-alive connection stays open (0 disables
+    // keep-alive).
+    pub workers: usize,
+    pub keep_alive: Duration,
+    // Mask IP addresses in logged details (`redact_secrets`), for
+    // environments where internal host addresses are sensitive.
+    pub mask_ips: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            query_timeout: Duration::from_secs(2),
+            expose_errors: false,
+            rate_limit_burst: 20,
+            rate_limit_per_sec: 5.0,
+            max_body_bytes: 16 * 1024,
+            shutdown_timeout: Duration::from_secs(30),
+            error_page_template: DEFAULT_ERROR_PAGE.to_string(),
+            stream_threshold: 100,
+            allowed_categories: ["books", "electronics", "garden", "toys"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            breaker_failure_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+            redacted_headers: ["authorization", "cookie", "x-api-key"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            not_found_body: "<h1>404 Not Found</h1>".to_string(),
+            not_found_content_type: "text/html; charset=utf-8".to_string(),
+            enforce_https: false,
+            canonical_host: None,
+            trusted_proxies: Vec::new(),
+            max_log_detail_len: DEFAULT_MAX_LOG_DETAIL_LEN,
+            maintenance_mode: false,
+            max_per_page: 1000,
+            scrubber_allowlist: Vec::new(),
+            slow_request_threshold: Duration::from_secs(1),
+            database_url: None,
+            error_format: ErrorFormatMode::Auto,
+            failure_capture_size: 0,
+            max_uri_len: 2048,
+            safe_validation_codes: DEFAULT_SAFE_VALIDATION_CODES
+                .iter()
+                .map(|code| code.to_string())
+                .collect(),
+            server_timing: false,
+            error_budget_max: 50,
+            error_budget_window: Duration::from_secs(60),
+            error_budget_block: false,
+            debug_token: None,
+            admin_token: None,
+            base64_min_len: DEFAULT_BASE64_MIN_LEN,
+            deprecated_paths: Vec::new(),
+            sunset: None,
+            strict_params: false,
+            workers: default_workers(),
+            keep_alive: Duration::from_secs(5),
+            mask_ips: false,
+        }
+    }
+}
+
+impl AppConfig {
+    // Build the configuration from environment variables:
+    // - `QUERY_TIMEOUT_MS`: database query timeout in milliseconds
+    // - `EXPOSE_ERRORS`: `true`/`1` to enable dev mode
+    // - `RATE_LIMIT_BURST`, `RATE_LIMIT_PER_SEC`: search rate limit per client IP
+    // - `MAX_BODY_BYTES`: largest accepted JSON request body
+    // - `SHUTDOWN_TIMEOUT_SECS`: grace period for draining requests on shutdown
+    // - `ERROR_PAGE_TEMPLATE` (inline) or `ERROR_PAGE_TEMPLATE_FILE` (path):
+    //   HTML error page template
+    // - `STREAM_THRESHOLD`: row count above which search results are streamed
+    // - `ALLOWED_CATEGORIES`: comma-separated list of searchable categories
+    // - `BREAKER_FAILURE_THRESHOLD`, `BREAKER_COOLDOWN_SECS`: database circuit breaker
+    // - `REDACTED_HEADERS`: comma-separated header names never logged verbatim
+    // - `NOT_FOUND_BODY`, `NOT_FOUND_CONTENT_TYPE`: the 404 page for unknown paths
+    // - `ENFORCE_HTTPS`: `true`/`1` to redirect HTTP to HTTPS and send HSTS
+    // - `CANONICAL_HOST`: host (and optional port) HTTPS redirects point at
+    // - `TRUSTED_PROXIES`: comma-separated proxy IPs whose forwarded headers count
+    // - `MAX_LOG_DETAIL_LEN`: bytes of an error detail kept in the log
+    // - `MAINTENANCE_MODE`: `true`/`1` to answer everything but `/health` with 503
+    // - `MAX_PER_PAGE`: cap on search results per page
+    // - `SCRUBBER_ALLOWLIST`: comma-separated strings exempt from secret scrubbing
+    // - `SLOW_REQUEST_MS`: handler duration above which a request is logged as slow
+    // - `DATABASE_URL`: database connection string (only used with `--features sqlx`)
+    // - `ERROR_FORMAT`: `json`, `html` or `auto` (negotiate, the default)
+    // - `FAILURE_CAPTURE_SIZE`: failed requests kept for `/admin/failed-requests`
+    // - `MAX_URI_LEN`: longest accepted request URI, in bytes
+    // - `SAFE_VALIDATION_CODES`: comma-separated validation codes clients may see
+    // - `SERVER_TIMING`: `true`/`1` to send `Server-Timing` on searches
+    // - `ERROR_BUDGET_MAX`, `ERROR_BUDGET_WINDOW_SECS`: 404/400 errors per client
+    //   before it's logged as a scanner; `ERROR_BUDGET_BLOCK` (`true`/`1`) to 429 it
+    // - `DEBUG_TOKEN`: bearer token opening `/debug/last-errors`
+    // - `ADMIN_TOKEN`: bearer token opening the admin endpoints
+    // - `BASE64_MIN_LEN`: shortest base64 run masked in logged details (0 = off)
+    // - `DEPRECATED_PATHS`: comma-separated paths answering with `Deprecation`
+    // - `SUNSET`: HTTP date sent as `Sunset` on deprecated paths
+    // - `STRICT_PARAMS`: `true`/`1` to reject unknown search query parameters
+    // - `WORKERS`: worker thread count (must be above 0)
+    // - `KEEP_ALIVE_SECS`: idle keep-alive timeout, 0 to disable keep-alive
+    // - `MASK_IPS`: `true`/`1` to mask IP addresses in logged details
+    pub fn from_env() -> Self {
+        let mut config = AppConfig::default();
+        if let Some(ms) = env_parse::<u64>("QUERY_TIMEOUT_MS") {
+            config.query_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = env_parse::<u64>("SLOW_REQUEST_MS") {
+            config.slow_request_threshold = Duration::from_millis(ms);
+        }
+        if let Some(burst) = env_parse::<u32>("RATE_LIMIT_BURST") {
+            config.rate_limit_burst = burst;
+        }
+        if let Some(rate) = env_parse::<f64>("RATE_LIMIT_PER_SEC").filter(|r| *r > 0.0) {
+            config.rate_limit_per_sec = rate;
+        }
+        if let Some(workers) = env_parse::<usize>("WORKERS") {
+            config.workers = clamp_workers(workers);
+        }
+        if let Some(secs) = env_parse::<u64>("KEEP_ALIVE_SECS") {
+            config.keep_alive = Duration::from_secs(secs);
+        }
+        if let Some(bytes) = env_parse::<usize>("MAX_BODY_BYTES").filter(|b| *b > 0) {
+            config.max_body_bytes = bytes;
+        }
+        if let Some(secs) = env_parse::<u64>("SHUTDOWN_TIMEOUT_SECS") {
+            config.shutdown_timeout = Duration::from_secs(secs);
+        }
+        if let Some(rows) = env_parse::<usize>("STREAM_THRESHOLD") {
+            config.stream_threshold = rows;
+        }
+        if let Some(failures) = env_parse::<u32>("BREAKER_FAILURE_THRESHOLD").filter(|f| *f > 0) {
+            config.breaker_failure_threshold = failures;
+        }
+        if let Some(secs) = env_parse::<u64>("BREAKER_COOLDOWN_SECS") {
+            config.breaker_cooldown = Duration::from_secs(secs);
+        }
+        if let Some(bytes) = env_parse::<usize>("MAX_LOG_DETAIL_LEN").filter(|b| *b > 0) {
+            config.max_log_detail_len = bytes;
+        }
+        if let Some(bytes) = env_parse::<usize>("MAX_URI_LEN").filter(|b| *b > 0) {
+            config.max_uri_len = bytes;
+        }
+        if let Some(errors) = env_parse::<usize>("ERROR_BUDGET_MAX") {
+            config.error_budget_max = errors;
+        }
+        if let Some(secs) = env_parse::<u64>("ERROR_BUDGET_WINDOW_SECS").filter(|s| *s > 0) {
+            config.error_budget_window = Duration::from_secs(secs);
+        }
+        if let Some(chars) = env_parse::<usize>("BASE64_MIN_LEN") {
+            config.base64_min_len = chars;
+        }
+        if let Some(entries) = env_parse::<usize>("FAILURE_CAPTURE_SIZE") {
+            config.failure_capture_size = entries;
+        }
+        if let Some(rows) = env_parse::<usize>("MAX_PER_PAGE").filter(|r| *r > 0) {
+            config.max_per_page = rows;
+        }
+        if let Ok(categories) = std::env::var("ALLOWED_CATEGORIES") {
+            config.allowed_categories = categories
+                .split(',')
+                .map(|c| c.trim().to_ascii_lowercase())
+                .filter(|c| !c.is_empty())
+                .collect();
+        }
+        if let Ok(headers) = std::env::var("REDACTED_HEADERS") {
+            config.redacted_headers = headers
+                .split(',')
+                .map(|h| h.trim().to_ascii_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect();
+        }
+        if let Ok(body) = std::env::var("NOT_FOUND_BODY") {
+            config.not_found_body = body;
+        }
+        if let Ok(content_type) = std::env::var("NOT_FOUND_CONTENT_TYPE") {
+            if !content_type.is_empty()
+                && content_type
+                    .chars()
+                    .all(|c| c.is_ascii_graphic() || c == ' ')
+            {
+                config.not_found_content_type = content_type;
+            } else {
+                warn!("Ignoring invalid NOT_FOUND_CONTENT_TYPE");
+            }
+        }
+        if let Ok(paths) = std::env::var("DEPRECATED_PATHS") {
+            config.deprecated_paths = paths
+                .split(',')
+                .map(|p| p.trim().trim_end_matches('/').to_string())
+                .filter(|p| !p.is_empty())
+                .collect();
+        }
+        if let Ok(host) = std::env::var("CANONICAL_HOST").map(|h| h.trim().to_string())
+            && !host.is_empty()
+        {
+            if is_valid_host(&host) {
+                config.canonical_host = Some(host);
+            } else {
+                warn!("Ignoring invalid CANONICAL_HOST");
+            }
+        }
+        if let Ok(proxies) = std::env::var("TRUSTED_PROXIES") {
+            for proxy in proxies.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                match proxy.parse() {
+                    Ok(ip) => config.trusted_proxies.push(ip),
+                    Err(_) => warn!("Ignoring invalid TRUSTED_PROXIES entry {:?}", proxy),
+                }
+            }
+        }
+        if let Ok(sunset) = std::env::var("SUNSET") {
+            if !sunset.is_empty() && sunset.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+                config.sunset = Some(sunset);
+            } else {
+                warn!("Ignoring invalid SUNSET");
+            }
+        }
+        if let Ok(codes) = std::env::var("SAFE_VALIDATION_CODES") {
+            config.safe_validation_codes = codes
+                .split(',')
+                .map(|c| c.trim().to_string())
+                .filter(|c| !c.is_empty())
+                .collect();
+        }
+        if let Ok(allowed) = std::env::var("SCRUBBER_ALLOWLIST") {
+            config.scrubber_allowlist = allowed
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect();
+        }
+        if let Ok(format) = std::env::var("ERROR_FORMAT") {
+            match ErrorFormatMode::parse(&format) {
+                Some(mode) => config.error_format = mode,
+                None => warn!("Ignoring invalid ERROR_FORMAT (expected json, html or auto)"),
+            }
+        }
+        if let Ok(token) = std::env::var("DEBUG_TOKEN").map(|t| t.trim().to_string())
+            && !token.is_empty()
+        {
+            config.debug_token = Some(SensitiveString::from(token));
+        }
+        if let Ok(token) = std::env::var("ADMIN_TOKEN").map(|t| t.trim().to_string())
+            && !token.is_empty()
+        {
+            config.admin_token = Some(SensitiveString::from(token));
+        }
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            config.database_url = Some(SensitiveString::from(url));
+        }
+        if let Ok(template) = std::env::var("ERROR_PAGE_TEMPLATE") {
+            config.error_page_template = template;
+        } else if let Ok(path) = std::env::var("ERROR_PAGE_TEMPLATE_FILE") {
+            match std::fs::read_to_string(&path) {
+                Ok(template) => config.error_page_template = template,
+                Err(e) => warn!("Could not read ERROR_PAGE_TEMPLATE_FILE {}: {}", path, e),
+            }
+        }
+        if let Ok(value) = std::env::var("ENFORCE_HTTPS") {
+            config.enforce_https = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("MAINTENANCE_MODE") {
+            config.maintenance_mode = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("ERROR_BUDGET_BLOCK") {
+            config.error_budget_block = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("MASK_IPS") {
+            config.mask_ips = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("STRICT_PARAMS") {
+            config.strict_params = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("SERVER_TIMING") {
+            config.server_timing = matches!(value.as_str(), "1" | "true");
+        }
+        if let Ok(value) = std::env::var("EXPOSE_ERRORS") {
+            config.expose_errors = matches!(value.as_str(), "1" | "true");
+        }
+        config
+    }
+}
+
+impl AppConfig {
+    // How `redact_secrets` treats this app's log details.
+    pub fn redaction(&self) -> RedactionOptions {
+        RedactionOptions {
+            base64_min_len: self.base64_min_len,
+            mask_ips: self.mask_ips,
+        }
+    }
+
+    // The configuration as JSON for `GET /config`. Every field is listed
+    // explicitly (no derived `Serialize`), so a new secret field can't show
+    // up here without someone deciding how to mask it.
+    pub fn to_safe_json(&self) -> Value {
+        let mut categories: Vec<&String> = self.allowed_categories.iter().collect();
+        categories.sort();
+        let mut headers: Vec<&String> = self.redacted_headers.iter().collect();
+        headers.sort();
+        let mut validation_codes: Vec<&String> = self.safe_validation_codes.iter().collect();
+        validation_codes.sort();
+        json!({
+            "query_timeout_ms": self.query_timeout.as_millis() as u64,
+            "expose_errors": self.expose_errors,
+            "rate_limit_burst": self.rate_limit_burst,
+            "rate_limit_per_sec": self.rate_limit_per_sec,
+            "max_body_bytes": self.max_body_bytes,
+            "shutdown_timeout_secs": self.shutdown_timeout.as_secs(),
+            "error_page_template": self.error_page_template,
+            "stream_threshold": self.stream_threshold,
+            "allowed_categories": categories,
+            "breaker_failure_threshold": self.breaker_failure_threshold,
+            "breaker_cooldown_secs": self.breaker_cooldown.as_secs(),
+            "redacted_headers": headers,
+            "not_found_body": self.not_found_body,
+            "not_found_content_type": self.not_found_content_type,
+            "enforce_https": self.enforce_https,
+            "canonical_host": self.canonical_host,
+            "trusted_proxies": self.trusted_proxies,
+            "max_log_detail_len": self.max_log_detail_len,
+            "maintenance_mode": self.maintenance_mode,
+            "max_per_page": self.max_per_page,
+            "scrubber_allowlist": self.scrubber_allowlist,
+            "slow_request_threshold_ms": self.slow_request_threshold.as_millis() as u64,
+            "database_url": self.database_url.as_ref().map(masked_url),
+            "error_format": self.error_format.as_str(),
+            "failure_capture_size": self.failure_capture_size,
+            "max_uri_len": self.max_uri_len,
+            "safe_validation_codes": validation_codes,
+            "server_timing": self.server_timing,
+            "error_budget_max": self.error_budget_max,
+            "error_budget_window_secs": self.error_budget_window.as_secs(),
+            "error_budget_block": self.error_budget_block,
+            "debug_token": self.debug_token.as_ref().map(SensitiveString::to_string),
+            "admin_token": self.admin_token.as_ref().map(SensitiveString::to_string),
+            "base64_min_len": self.base64_min_len,
+            "deprecated_paths": self.deprecated_paths,
+            "sunset": self.sunset,
+            "strict_params": self.strict_params,
+            "workers": self.workers,
+            "keep_alive_secs": self.keep_alive.as_secs(),
+            "mask_ips": self.mask_ips,
+        })
+    }
+}
+
+// Keep only the scheme of a connection string, e.g. `postgres://[REDACTED]`:
+// enough to see which kind of database is configured, but no host, user or
+// password.
+fn masked_url(url: &SensitiveString) -> String {
+    match url.expose_secret().split_once("://") {
+        Some((scheme, _))
+            if scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+') =>
+        {
+            format!("{}://{}", scheme, url)
+        }
+        _ => url.to_string(),
+    }
+}
+
+// GET /config: the running configuration with its secrets masked, so
+// operators can check settings without reading credentials. Even masked,
+// the settings map out the deployment, so it needs the admin token.
+pub async fn config_endpoint(
+    req: HttpRequest,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    Ok(HttpResponse::Ok().json(config.to_safe_json()))
+}
+
+// A bare host name or IP, optionally with a port: nothing that could turn a
+// redirect into a different URL (no scheme, path, userinfo or whitespace).
+fn is_valid_host(host: &str) -> bool {
+    host.len() <= 253
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+// One worker per CPU, or a single worker if that can't be determined.
+fn default_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// A `WORKERS` setting as a worker count: 0 would leave nobody to serve
+// requests, so it falls back to `default_workers` with a warning.
+fn clamp_workers(workers: usize) -> usize {
+    if workers > 0 {
+        return workers;
+    }
+    let workers = default_workers();
+    warn!("Ignoring WORKERS=0: using one worker per CPU ({})", workers);
+    workers
+}
+
+// Read and parse an environment variable, ignoring it if unset or invalid.
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_workers_falls_back_to_the_default() {
+        assert_eq!(clamp_workers(0), default_workers());
+        assert!(clamp_workers(0) > 0);
+        assert_eq!(clamp_workers(3), 3);
+    }
+}
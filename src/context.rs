@@ -0,0 +1,80 @@
+// =========================================================================
+// --- Per-Request Context for Error Rendering ---
+// =========================================================================
+
+// `ResponseError::error_response(&self)` only receives the error itself, but
+// rendering an error safely sometimes needs request-scoped data (headers such
+// as `Accept`, the request id, shared `web::Data`). This middleware captures
+// that data in a task-local for the duration of the call, so
+// `error_response()` can look it up.
+
+use actix_web::{
+    Error, HttpMessage,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::HeaderMap,
+    middleware::Next,
+    web,
+};
+
+use std::cell::Cell;
+
+use crate::audit::AuditLog;
+use crate::config::AppConfig;
+use crate::metrics::Metrics;
+use crate::middleware::RequestId;
+use crate::redaction::SecretScrubber;
+use crate::reporting::ErrorReporter;
+
+// What error rendering may need to know about the current request.
+pub struct RequestContext {
+    pub headers: HeaderMap,
+    pub request_id: Option<String>,
+    pub scrubber: Option<web::Data<SecretScrubber>>,
+    pub config: Option<web::Data<AppConfig>>,
+    pub metrics: Option<web::Data<Metrics>>,
+    pub reporter: Option<web::Data<ErrorReporter>>,
+    pub audit: Option<web::Data<AuditLog>>,
+    // Set by `json_errors` for routes whose errors must always be JSON.
+    pub json_errors: Cell<bool>,
+}
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+// Middleware: make a `RequestContext` available to `with_current()` while
+// the rest of the service chain (including error rendering) runs.
+// Must be wrapped inside `middleware::request_id` so the id is already set.
+pub async fn request_context(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let ctx = RequestContext {
+        headers: req.headers().clone(),
+        request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+        scrubber: req.app_data::<web::Data<SecretScrubber>>().cloned(),
+        config: req.app_data::<web::Data<AppConfig>>().cloned(),
+        metrics: req.app_data::<web::Data<Metrics>>().cloned(),
+        reporter: req.app_data::<web::Data<ErrorReporter>>().cloned(),
+        audit: req.app_data::<web::Data<AuditLog>>().cloned(),
+        json_errors: Cell::new(false),
+    };
+    CURRENT.scope(ctx, next.call(req)).await
+}
+
+// Route middleware: render this route's errors as JSON regardless of the
+// `Accept` header, so JSON endpoints never answer with an HTML error page.
+pub async fn json_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    with_current(|ctx| ctx.json_errors.set(true));
+    next.call(req).await
+}
+
+// Run `f` with the context of the request being handled, if called from
+// within `request_context`. Returns `None` otherwise (e.g. at startup).
+pub fn with_current<R>(f: impl FnOnce(&RequestContext) -> R) -> Option<R> {
+    CURRENT.try_with(f).ok()
+}
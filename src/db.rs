@@ -0,0 +1,226 @@
+// =========================================================================
+// --- Real Database Access (enabled with `--features sqlx`) ---
+// =========================================================================
+
+use actix_web::{HttpResponse, web};
+use log::{error, info};
+use sqlx::PgPool;
+use sqlx::postgres::PgPoolOptions;
+use std::time::Duration;
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::AppConfig;
+use crate::redaction::sanitize_for_log;
+use crate::repository::ProductRepository;
+use crate::{AppError, SearchQuery, check_authorization, html_escape, search_products};
+
+// Convert driver errors into our `AppError` so handlers can simply use `?`.
+// The driver error (whose message can include hostnames, SQL text or
+// credentials) is kept in the `DbError` detail for the internal log only.
+// Lost connections and pool exhaustion are transient (worth a retry);
+// database errors with a SQLSTATE in `SQLSTATE_MAP` get the status it
+// names (see `map_sqlstate`), and everything else is a permanent
+// `DbError`.
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => {
+                AppError::NotFound("query returned no rows (sqlx::Error::RowNotFound)".to_string())
+            }
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                AppError::transient_db(format!("database connection failed: {}", e))
+            }
+            sqlx::Error::Database(ref db)
+                if db
+                    .code()
+                    .is_some_and(|code| sqlstate_mapping(&code).is_some()) =>
+            {
+                map_sqlstate(&db.code().unwrap_or_default())
+            }
+            sqlx::Error::Database(ref db) if constraint_violated(db.kind()) => {
+                AppError::internal(format!("database constraint violated: {}", e))
+            }
+            other => AppError::internal(format!("database query failed: {}", other)),
+        }
+    }
+}
+
+// What a SQLSTATE means for the client.
+#[derive(Clone, Copy, Debug)]
+enum SqlStateMapping {
+    // 409: the row already exists.
+    Conflict,
+    // 504: the query was cancelled, usually by `statement_timeout`.
+    Timeout,
+    // 503: the database is unreachable or refusing connections.
+    Unavailable,
+}
+
+// SQLSTATE codes (or two-character classes, which cover every code in the
+// class) and how they map. Exact codes are checked before classes; add
+// entries here to map more codes.
+const SQLSTATE_MAP: &[(&str, SqlStateMapping)] = &[
+    // unique_violation
+    ("23505", SqlStateMapping::Conflict),
+    // query_canceled
+    ("57014", SqlStateMapping::Timeout),
+    // Class 08: connection exception
+    ("08", SqlStateMapping::Unavailable),
+];
+
+fn sqlstate_mapping(code: &str) -> Option<SqlStateMapping> {
+    let exact = SQLSTATE_MAP.iter().find(|(entry, _)| *entry == code);
+    let class = || {
+        SQLSTATE_MAP
+            .iter()
+            .find(|(entry, _)| entry.len() == 2 && code.starts_with(entry))
+    };
+    exact.or_else(class).map(|(_, mapping)| *mapping)
+}
+
+// The `AppError` for a SQLSTATE code. The detail (for the log only) names
+// just the code; the client sees the variant's generic message. Codes not
+// in `SQLSTATE_MAP` are permanent database errors (a 500).
+pub fn map_sqlstate(code: &str) -> AppError {
+    let detail = format!("database reported SQLSTATE {}", sanitize_for_log(code));
+    match sqlstate_mapping(code) {
+        Some(SqlStateMapping::Conflict) => AppError::Conflict(detail),
+        Some(SqlStateMapping::Timeout) => AppError::Timeout(detail),
+        Some(SqlStateMapping::Unavailable) => AppError::ServiceUnavailable {
+            detail,
+            retry_after_secs: SQLSTATE_RETRY_AFTER_SECS,
+        },
+        None => AppError::internal(detail),
+    }
+}
+
+// What clients are told to wait after a connection exception.
+const SQLSTATE_RETRY_AFTER_SECS: u64 = 5;
+
+fn constraint_violated(kind: sqlx::error::ErrorKind) -> bool {
+    use sqlx::error::ErrorKind;
+    matches!(
+        kind,
+        ErrorKind::UniqueViolation
+            | ErrorKind::ForeignKeyViolation
+            | ErrorKind::NotNullViolation
+            | ErrorKind::CheckViolation
+    )
+}
+
+// Build a connection pool from `DATABASE_URL`, if one is configured.
+// The pool connects lazily, so the server still starts when the database is down.
+pub fn pool_from_config(config: &AppConfig) -> Option<PgPool> {
+    let url = config.database_url.as_ref()?;
+    match PgPoolOptions::new().connect_lazy(url.expose_secret()) {
+        Ok(pool) => Some(pool),
+        Err(e) => {
+            // Never log the URL itself, it usually contains credentials.
+            error!("SECURE (internal log): Invalid DATABASE_URL: {}", e);
+            None
+        }
+    }
+}
+
+// Check that the database answers a trivial query within `limit`.
+pub async fn ping(pool: &PgPool, limit: Duration) -> Result<(), AppError> {
+    match tokio::time::timeout(limit, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(result) => result.map(|_| ()).map_err(AppError::from),
+        Err(_) => Err(AppError::Timeout(format!(
+            "database ping still running after {:?}",
+            limit
+        ))),
+    }
+}
+
+// Same contract as `secure_search`, but only mounted with a real database.
+// The repository is then a `SqlxRepository` (see `AppState::new`), so the
+// query gets the same circuit breaker, retries and timeout as any search,
+// and driver errors are converted by `?` inside it.
+async fn secure_search_db(
+    config: web::Data<AppConfig>,
+    breaker: web::Data<CircuitBreaker>,
+    repository: web::Data<dyn ProductRepository>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, AppError> {
+    check_authorization(&query, &config)?;
+    info!(
+        "Received secure DB search request for: {}",
+        sanitize_for_log(&query.product)
+    );
+    query.validate()?;
+    search_products(&query, &config, &breaker, &**repository).await?;
+    Ok(HttpResponse::Ok().body(format!(
+        "<h1>Search Result</h1><p>Successfully retrieved products for: {}</p>",
+        html_escape(&query.product)
+    )))
+}
+
+// Register the database-backed routes when a pool is available.
+pub fn configure(cfg: &mut web::ServiceConfig, pool: Option<PgPool>) {
+    if let Some(pool) = pool {
+        cfg.app_data(web::Data::new(pool))
+            .service(crate::resource("/secure-search-db").route(web::get().to(secure_search_db)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DbErrorKind;
+    use actix_web::ResponseError;
+
+    // Driver errors without a more specific mapping are permanent database
+    // errors: a generic 500, with the driver's message kept for the log.
+    #[test]
+    fn unmapped_driver_errors_are_db_errors() {
+        let e = AppError::from(sqlx::Error::Protocol(
+            "unexpected reply from postgres://admin:supersecret@db:5432".to_string(),
+        ));
+        let AppError::DbError { kind, detail } = &e else {
+            panic!("{:?}", e);
+        };
+        assert_eq!(*kind, DbErrorKind::Permanent);
+        assert!(detail.expose_secret().contains("unexpected reply"));
+        assert_eq!(
+            e.status_code(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(!e.user_message().contains("postgres"));
+    }
+
+    // Each SQLSTATE in the table gets its status, a class entry covers
+    // every code in the class, and anything else is a permanent 500.
+    #[test]
+    fn sqlstates_map_to_their_statuses() {
+        use actix_web::http::StatusCode;
+
+        let cases = [
+            ("23505", StatusCode::CONFLICT),
+            ("57014", StatusCode::GATEWAY_TIMEOUT),
+            ("08006", StatusCode::SERVICE_UNAVAILABLE),
+            ("08001", StatusCode::SERVICE_UNAVAILABLE),
+            ("42601", StatusCode::INTERNAL_SERVER_ERROR),
+            ("", StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (code, status) in cases {
+            assert_eq!(map_sqlstate(code).status_code(), status, "{}", code);
+        }
+        assert!(matches!(map_sqlstate("23505"), AppError::Conflict(_)));
+        assert!(matches!(map_sqlstate("57014"), AppError::Timeout(_)));
+        assert!(matches!(
+            map_sqlstate("08006"),
+            AppError::ServiceUnavailable {
+                retry_after_secs: SQLSTATE_RETRY_AFTER_SECS,
+                ..
+            }
+        ));
+        assert!(matches!(
+            map_sqlstate("42601"),
+            AppError::DbError {
+                kind: DbErrorKind::Permanent,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+// =========================================================================
+// --- Per-Client Error Budget ---
+// =========================================================================
+
+// Counts the 404s and 400s (`AppError::NotFound`, `AppError::Validation`,
+// unknown paths) each client IP causes within a sliding window. A client
+// that burns through its budget is probably scanning or enumerating, so we
+// log an audit warning once per window, naming only the IP and the count.
+// With `error_budget_block` on, such a client gets 429s until its window
+// has room again. Shared by all workers via `web::Data`.
+
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    middleware::Next,
+    web,
+};
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::AppConfig;
+use crate::{AppError, logging};
+
+// Stop tracking quiet clients once this many are tracked.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+pub struct ErrorBudget {
+    max_errors: usize,
+    window: Duration,
+    // When each client's recent errors happened, oldest first.
+    clients: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl ErrorBudget {
+    // `max_errors` errors per `window` are allowed; 0 turns counting off.
+    pub fn new(max_errors: usize, window: Duration) -> Self {
+        ErrorBudget {
+            max_errors,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether `ip` has used up its budget for the current window.
+    fn exhausted(&self, ip: IpAddr) -> bool {
+        if self.max_errors == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.get_mut(&ip).is_some_and(|errors| {
+            self.expire(errors, now);
+            errors.len() >= self.max_errors
+        })
+    }
+
+    // Count an error for `ip`, and warn when it just used up its budget.
+    fn record(&self, ip: IpAddr) {
+        if self.max_errors == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            // Forget clients with no errors left in their window.
+            clients.retain(|_, errors| {
+                errors
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < self.window)
+            });
+        }
+
+        let errors = clients.entry(ip).or_default();
+        self.expire(errors, now);
+        errors.push_back(now);
+        if errors.len() == self.max_errors {
+            warn!(
+                target: logging::AUDIT_TARGET,
+                "Client {} caused {} not-found/validation errors within {:?}: possible scanning",
+                ip,
+                errors.len(),
+                self.window
+            );
+        }
+    }
+
+    // Drop errors older than the window.
+    fn expire(&self, errors: &mut VecDeque<Instant>, now: Instant) {
+        while errors
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            errors.pop_front();
+        }
+    }
+}
+
+// App middleware: count 404/400 responses against the client's budget and,
+// if configured to, refuse clients that have used it up.
+pub async fn error_budget(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let budget = req.app_data::<web::Data<ErrorBudget>>().cloned();
+    let block = req
+        .app_data::<web::Data<AppConfig>>()
+        .is_some_and(|config| config.error_budget_block);
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let (Some(budget), Some(ip)) = (budget, ip) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    if block && budget.exhausted(ip) {
+        let e = AppError::RateLimited {
+            detail: format!("client {} exhausted its error budget", ip),
+            retry_after_secs: budget.window.as_secs().max(1),
+        };
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+
+    let res = next.call(req).await?;
+    if matches!(
+        res.status(),
+        StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST
+    ) {
+        budget.record(ip);
+    }
+    Ok(res.map_into_left_body())
+}
@@ -0,0 +1,151 @@
+// =========================================================================
+// --- Failed Request Capture (for debugging) ---
+// =========================================================================
+
+// With `AppConfig::failure_capture_size` above zero, the query string and
+// body of every request that ended in an error are kept in a small ring
+// buffer, newest last, for `GET /admin/failed-requests`. Everything stored
+// has been through `redact_secrets` and is cut to `max_log_detail_len`
+// bytes, so the buffer holds no more than the log would. Off by default.
+
+use actix_web::{
+    Error, HttpMessage, HttpRequest, HttpResponse,
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web::{self, Bytes},
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::audit::unix_timestamp;
+use crate::config::AppConfig;
+use crate::middleware::RequestId;
+use crate::redaction::{redact_secrets_with, sanitize_detail_for_log};
+use crate::response::ERROR_REFERENCE_HEADER;
+use crate::{AppError, require_admin};
+
+// One failed request, already redacted.
+#[derive(Clone, Serialize)]
+pub struct CapturedRequest {
+    // Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub request_id: Option<String>,
+    pub reference: Option<String>,
+    pub status: u16,
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    // `None` when the body wasn't read: it had no declared length, or one
+    // over `max_body_bytes`.
+    pub body: Option<String>,
+}
+
+pub struct FailureCapture {
+    capacity: usize,
+    entries: Mutex<VecDeque<CapturedRequest>>,
+}
+
+impl FailureCapture {
+    pub fn new(capacity: usize) -> Self {
+        FailureCapture {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // Store `entry`, dropping the oldest one when full.
+    fn push(&self, entry: CapturedRequest) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn snapshot(&self) -> Vec<CapturedRequest> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().cloned().collect()
+    }
+}
+
+// Middleware: buffer the (small) request body so it can be put back for the
+// handler, and record the request in `FailureCapture` if the response is an
+// error. Does nothing unless capture is enabled.
+pub async fn capture_failed_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let capture = req.app_data::<web::Data<FailureCapture>>().cloned();
+    let config = req.app_data::<web::Data<AppConfig>>().cloned();
+    let (Some(capture), Some(config)) = (capture, config) else {
+        return next.call(req).await;
+    };
+    if !capture.is_enabled() {
+        return next.call(req).await;
+    }
+
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<usize>().ok());
+    let mut body = None;
+    if let Some(length) = declared.filter(|length| *length <= config.max_body_bytes) {
+        let bytes = req.extract::<Bytes>().await.unwrap_or_default();
+        if length > 0 {
+            body = Some(clean(&String::from_utf8_lossy(&bytes), &config));
+        }
+        req.set_payload(Payload::from(bytes));
+    }
+    let method = req.method().to_string();
+    let path = clean(req.path(), &config);
+    let query = clean(req.query_string(), &config);
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+
+    let res = next.call(req).await?;
+
+    let status = res.status();
+    if status.is_client_error() || status.is_server_error() {
+        let reference = res
+            .headers()
+            .get(ERROR_REFERENCE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        capture.push(CapturedRequest {
+            timestamp: unix_timestamp(),
+            request_id,
+            reference,
+            status: status.as_u16(),
+            method,
+            path,
+            query,
+            body,
+        });
+    }
+    Ok(res)
+}
+
+// Runs outside `request_context`, so the redaction options are passed in.
+fn clean(text: &str, config: &AppConfig) -> String {
+    sanitize_detail_for_log(
+        &redact_secrets_with(text, config.redaction()),
+        config.max_log_detail_len,
+    )
+}
+
+// GET /admin/failed-requests: the captured requests as JSON, oldest first.
+// Requires the admin bearer token, like `/secure-admin`.
+pub async fn failed_requests_endpoint(
+    req: HttpRequest,
+    capture: web::Data<FailureCapture>,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&req)?;
+    Ok(HttpResponse::Ok().json(capture.snapshot()))
+}
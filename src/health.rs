@@ -0,0 +1,73 @@
+// =========================================================================
+// --- Health Check ---
+// =========================================================================
+
+// GET /health reports whether our dependencies are reachable. Only an
+// up/down status per dependency is returned; why a check failed (which can
+// include hostnames or connection strings) goes to the log, redacted.
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use log::warn;
+use serde::Serialize;
+
+use crate::config::AppConfig;
+use crate::repository::ProductRepository;
+use crate::{AppError, scrub_detail};
+
+// The product looked up to check that the (simulated) database answers.
+const PROBE_PRODUCT: &str = "health-check";
+
+#[derive(Serialize)]
+struct HealthReport {
+    status: &'static str,
+    checks: Checks,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: &'static str,
+}
+
+// Ping the real database when one is configured, the simulated one otherwise.
+async fn ping_database(req: &HttpRequest, config: &AppConfig) -> Result<(), AppError> {
+    #[cfg(feature = "sqlx")]
+    if let Some(pool) = req.app_data::<web::Data<sqlx::PgPool>>() {
+        return crate::db::ping(pool, config.query_timeout).await;
+    }
+    let repository = req
+        .app_data::<web::Data<dyn ProductRepository>>()
+        .ok_or_else(|| AppError::internal("no product repository registered"))?;
+    crate::query_with_timeout(
+        &***repository,
+        PROBE_PRODUCT,
+        crate::Page::ALL,
+        config.query_timeout,
+    )
+    .await
+    .map(|_| ())
+}
+
+// 200 with `"status":"ok"` when every check passes, 503 with
+// `"status":"degraded"` otherwise, so load balancers can act on it.
+pub async fn health(req: HttpRequest, config: web::Data<AppConfig>) -> HttpResponse {
+    let database = match ping_database(&req, &config).await {
+        Ok(()) => "up",
+        Err(e) => {
+            warn!(
+                detail = scrub_detail(&e.to_string()).as_str();
+                "SECURE (internal log): Health check: database is down"
+            );
+            "down"
+        }
+    };
+
+    let report = HealthReport {
+        status: if database == "up" { "ok" } else { "degraded" },
+        checks: Checks { database },
+    };
+    if report.status == "ok" {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
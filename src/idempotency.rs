@@ -0,0 +1,273 @@
+// =========================================================================
+// --- Idempotency Keys for Create Endpoints ---
+// =========================================================================
+
+// A client that retries `POST /products` (say, after a timeout) can send the
+// same `Idempotency-Key` header both times: the second request gets the
+// first one's response, status, headers and body, instead of creating the
+// product again (or failing with a 409). Errors are replayed too, as the
+// already-rendered client response, so nothing beyond it is ever stored.
+// A key comes with a hash of its request's body: sending the key again with
+// a different body is a client bug, answered with a 422 instead of someone
+// else's response. Keys are scoped to the client that sent them (see
+// `client_scope`), so guessing another client's key replays nothing. The
+// store is in memory and bounded: the oldest keys are forgotten first.
+
+use actix_web::{
+    Error, HttpMessage, HttpResponse, ResponseError,
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{
+        StatusCode,
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+    },
+    middleware::Next,
+    web::{self, Bytes},
+};
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::config::AppConfig;
+use crate::{AppError, FieldError};
+
+pub const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+// Added to replayed responses, so clients can tell them apart.
+const REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+// How many keys are remembered, and the longest key accepted.
+const MAX_STORED_KEYS: usize = 1024;
+const MAX_KEY_LEN: usize = 255;
+// Responses with bigger bodies aren't stored (create responses are tiny).
+const MAX_STORED_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+// SHA-256 of a request body.
+type BodyHash = [u8; 32];
+
+enum Entry {
+    // The first request with this key is still running.
+    InFlight(BodyHash),
+    Done(BodyHash, StoredResponse),
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    // Keys in insertion order, oldest first, for eviction.
+    order: VecDeque<String>,
+}
+
+#[derive(Default)]
+pub struct IdempotencyStore {
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyStore {
+    // Claim `key` for a new request with body hash `body`, or return what
+    // an earlier request with the same key and body answered. `Err` if that
+    // request is still running, or had a different body.
+    fn begin(&self, key: &str, body: BodyHash) -> Result<Option<StoredResponse>, AppError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.by_key.get(key) {
+            Some(Entry::InFlight(hash) | Entry::Done(hash, _)) if *hash != body => {
+                return Err(AppError::IdempotencyKeyReused(format!(
+                    "{} was sent again with a different body",
+                    key
+                )));
+            }
+            Some(Entry::Done(_, stored)) => return Ok(Some(stored.clone())),
+            Some(Entry::InFlight(_)) => {
+                return Err(AppError::Conflict(
+                    "idempotency key is in use by a request still running".to_string(),
+                ));
+            }
+            None => {}
+        }
+        while entries.order.len() >= MAX_STORED_KEYS {
+            match entries.order.pop_front() {
+                Some(oldest) => {
+                    entries.by_key.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        entries
+            .by_key
+            .insert(key.to_string(), Entry::InFlight(body));
+        entries.order.push_back(key.to_string());
+        Ok(None)
+    }
+
+    fn finish(&self, key: &str, stored: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.by_key.get_mut(key)
+            && let Entry::InFlight(hash) = *entry
+        {
+            *entry = Entry::Done(hash, stored);
+        }
+    }
+
+    // Forget a key whose request never produced a storable response.
+    fn abandon(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(Entry::InFlight(_)) = entries.by_key.get(key) {
+            entries.by_key.remove(key);
+            entries.order.retain(|k| k != key);
+        }
+    }
+}
+
+// Releases the key if the request is dropped (e.g. the client went away)
+// before its response was stored.
+struct Claim<'a> {
+    store: &'a IdempotencyStore,
+    key: &'a str,
+    finished: bool,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.store.abandon(self.key);
+        }
+    }
+}
+
+// Who a key belongs to: the caller's credentials if it sent any (as a hash,
+// so they're never stored), otherwise its IP address.
+fn client_scope(req: &ServiceRequest) -> String {
+    if let Some(credentials) = req.headers().get(header::AUTHORIZATION) {
+        let hash = Sha256::digest(credentials.as_bytes());
+        let hex: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        return format!("auth:{}", hex);
+    }
+    match req.peer_addr() {
+        Some(addr) => format!("ip:{}", addr.ip()),
+        None => "-".to_string(),
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && key.chars().all(|c| c.is_ascii_graphic())
+}
+
+// Resource middleware: honor `Idempotency-Key` on the wrapped (create)
+// endpoint. Requests without the header run as usual. Must run inside
+// `request_context`, so its own errors render like any other.
+pub async fn idempotency(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    };
+    let Some(store) = req.app_data::<web::Data<IdempotencyStore>>().cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    };
+    let key = match value.to_str() {
+        // Scoped to the client and the endpoint, so one key can't replay
+        // another client's or another route's answer
+        Ok(key) if is_valid_key(key) => format!(
+            "{} {} {} {}",
+            client_scope(&req),
+            req.method(),
+            req.path(),
+            key
+        ),
+        _ => {
+            let e = AppError::Validation(vec![FieldError::new("idempotency-key", "invalid")]);
+            return Ok(req.error_response(e).map_into_boxed_body());
+        }
+    };
+
+    // Read the body to hash it, then hand it back to the handler. It's
+    // capped like any JSON body.
+    let limit = req
+        .app_data::<web::Data<AppConfig>>()
+        .map_or(usize::MAX, |config| config.max_body_bytes);
+    let bytes = match read_body(req.take_payload(), limit).await {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(req.error_response(e).map_into_boxed_body()),
+    };
+    let body_hash: BodyHash = Sha256::digest(&bytes).into();
+    req.set_payload(Payload::from(bytes));
+
+    match store.begin(&key, body_hash) {
+        Ok(Some(stored)) => return Ok(req.into_response(replay(stored))),
+        Ok(None) => {}
+        Err(e) => return Ok(req.error_response(e).map_into_boxed_body()),
+    }
+    let mut claim = Claim {
+        store: &store,
+        key: &key,
+        finished: false,
+    };
+
+    let res = next.call(req).await?;
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = match body::to_bytes_limited(body, MAX_STORED_BODY_BYTES).await {
+        Ok(Ok(bytes)) => bytes,
+        // The body is gone at this point, so answer with a generic error.
+        Ok(Err(_)) | Err(_) => {
+            return Ok(ServiceResponse::new(
+                req,
+                AppError::GenericError(None).error_response(),
+            ));
+        }
+    };
+    store.finish(
+        &key,
+        StoredResponse {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: bytes.clone(),
+        },
+    );
+    claim.finished = true;
+    Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(bytes))))
+}
+
+// The whole request body, or `PayloadTooLarge` past `limit` bytes.
+async fn read_body(mut payload: Payload, limit: usize) -> Result<Bytes, AppError> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| AppError::Wrapped {
+            context: "could not read body for idempotency check".to_string(),
+            source: crate::ErrorSource::new(e),
+        })?;
+        if body.len() + chunk.len() > limit {
+            return Err(AppError::PayloadTooLarge(format!(
+                "body exceeded limit of {} bytes while reading",
+                limit
+            )));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+fn replay(stored: StoredResponse) -> HttpResponse {
+    let mut response = HttpResponse::build(stored.status).body(stored.body);
+    for (name, value) in stored.headers.iter() {
+        response.headers_mut().append(name.clone(), value.clone());
+    }
+    response
+        .headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
@@ -0,0 +1,313 @@
+// =========================================================================
+// --- Logging Setup ---
+// =========================================================================
+
+// Log lines are written as JSON objects, one per line. Structured fields
+// attached to a record (e.g. `request.id`, `error.variant`, `detail`) become
+// top-level keys, which makes it easy to check that `detail` was redacted:
+//
+// {"level":"error","request.id":"...","error.variant":"db_error","error.status":"500","detail":"...","message":"..."}
+//
+// Set `LOG_FORMAT=text` for the classic human-readable env_logger format.
+//
+// Security-relevant events (auth failures, rate limiting) are logged to the
+// `audit` target, everything else from error rendering to `app`. Set
+// `AUDIT_LOG_FILE` to send the audit target to its own file; otherwise both
+// go to stderr.
+//
+// With the `tracing` feature, `init_tracing` sets up a `tracing` subscriber
+// instead, and every request runs in a span (see `middleware::trace_request`).
+
+use env_logger::{Logger, Target};
+use log::kv::{Error as KvError, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, warn};
+use serde_json::{Map, Value as JsonValue};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::redaction::sanitize_for_log;
+
+pub const AUDIT_TARGET: &str = "audit";
+pub const APP_TARGET: &str = "app";
+
+// Collects a record's key-values into a JSON object.
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        self.0
+            .insert(key.to_string(), JsonValue::String(value.to_string()));
+        Ok(())
+    }
+}
+
+// Sends `audit` records to their own logger (when configured) and
+// everything else to the main one.
+struct SplitLogger {
+    app: Logger,
+    audit: Option<Logger>,
+}
+
+impl SplitLogger {
+    fn logger_for(&self, target: &str) -> &Logger {
+        match &self.audit {
+            Some(audit) if target == AUDIT_TARGET => audit,
+            _ => &self.app,
+        }
+    }
+}
+
+impl Log for SplitLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger_for(metadata.target()).enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.logger_for(record.target()).log(record)
+    }
+
+    fn flush(&self) {
+        self.app.flush();
+        if let Some(audit) = &self.audit {
+            audit.flush();
+        }
+    }
+}
+
+// Why `init_logging` couldn't set up logging.
+#[cfg_attr(feature = "tracing", allow(dead_code))]
+#[derive(Debug, thiserror::Error)]
+pub enum LogInitError {
+    #[error("invalid default log level {0:?}")]
+    InvalidDefaultLevel(String),
+    #[error("a global logger is already installed")]
+    AlreadyInitialized,
+}
+
+// Whether `spec` is a filter env_logger understands: comma-separated
+// `level`, `module` or `module=level` directives, optionally followed by
+// `/regex`. env_logger itself skips what it can't parse, which would
+// quietly log more (or less) than intended.
+fn is_valid_filter(spec: &str) -> bool {
+    let directives = spec
+        .split_once('/')
+        .map_or(spec, |(directives, _)| directives);
+    directives
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .all(|directive| match directive.split_once('=') {
+            Some((module, level)) => is_module(module) && level.parse::<LevelFilter>().is_ok(),
+            None => directive.parse::<LevelFilter>().is_ok() || is_module(directive),
+        })
+}
+
+fn is_module(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':' || c == '-')
+}
+
+// The filter to log with: `requested` (from `RUST_LOG`) when it's set and
+// well-formed, `default_level` otherwise. The second value is the rejected
+// spec, if `requested` was malformed, so the caller can warn about it.
+fn choose_filter<'a>(
+    requested: Option<&'a str>,
+    default_level: &'a str,
+) -> (&'a str, Option<&'a str>) {
+    match requested.filter(|spec| !spec.trim().is_empty()) {
+        Some(spec) if is_valid_filter(spec) => (spec, None),
+        Some(spec) => (default_level, Some(spec)),
+        None => (default_level, None),
+    }
+}
+
+// Configure the global logger. Call once, at startup. `RUST_LOG` picks the
+// filter; when it's unset, or malformed (which is logged as a warning),
+// `default_level` is used instead.
+#[cfg_attr(feature = "tracing", allow(dead_code))]
+pub fn init_logging(default_level: &str) -> Result<(), LogInitError> {
+    if default_level.parse::<LevelFilter>().is_err() {
+        return Err(LogInitError::InvalidDefaultLevel(default_level.to_string()));
+    }
+    let requested = std::env::var("RUST_LOG").ok();
+    let (filter, rejected) = choose_filter(requested.as_deref(), default_level);
+
+    let app = builder(filter).build();
+    let mut audit_error = None;
+    let audit = std::env::var("AUDIT_LOG_FILE").ok().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(builder(filter).target(Target::Pipe(Box::new(file))).build()),
+            Err(e) => {
+                audit_error = Some(format!("Could not open AUDIT_LOG_FILE {}: {}", path, e));
+                None
+            }
+        }
+    });
+
+    let max_level = audit
+        .as_ref()
+        .map_or(app.filter(), |audit| app.filter().max(audit.filter()));
+    log::set_boxed_logger(Box::new(SplitLogger { app, audit }))
+        .map_err(|_| LogInitError::AlreadyInitialized)?;
+    log::set_max_level(max_level);
+    if let Some(spec) = rejected {
+        warn!(
+            "Ignoring malformed RUST_LOG {:?}: logging at {} instead",
+            sanitize_for_log(spec),
+            default_level
+        );
+    }
+    if let Some(e) = audit_error {
+        warn!("{}", e);
+    }
+    Ok(())
+}
+
+// An env_logger builder with the given filter and our line format.
+fn builder(filter: &str) -> env_logger::Builder {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filter);
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        builder.parse_write_style(&style);
+    }
+
+    if std::env::var("LOG_FORMAT").as_deref() != Ok("text") {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert(
+                "level".to_string(),
+                JsonValue::String(record.level().as_str().to_lowercase()),
+            );
+            line.insert(
+                "timestamp".to_string(),
+                JsonValue::String(buf.timestamp().to_string()),
+            );
+            line.insert(
+                "target".to_string(),
+                JsonValue::String(record.target().to_string()),
+            );
+            let _ = record.key_values().visit(&mut JsonFields(&mut line));
+            line.insert(
+                "message".to_string(),
+                JsonValue::String(record.args().to_string()),
+            );
+            writeln!(buf, "{}", JsonValue::Object(line))
+        });
+    }
+
+    builder
+}
+
+// The `tracing` alternative to `init_logging`: JSON lines (or text with
+// `LOG_FORMAT=text`) filtered by `RUST_LOG`. Events from the `log` macros
+// are forwarded to it, so nothing goes missing; `AUDIT_LOG_FILE` isn't
+// supported here, audit events are told apart by their target instead.
+#[cfg(feature = "tracing")]
+pub fn init_tracing() {
+    use tracing_subscriber::EnvFilter;
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = if std::env::var("LOG_FORMAT").as_deref() == Ok("text") {
+        subscriber.try_init()
+    } else {
+        subscriber.json().try_init()
+    };
+    if let Err(e) = result {
+        eprintln!("Could not set up tracing: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Collects what a logger writes.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What our default (JSON) format writes for `record`.
+    fn written(record: &Record) -> String {
+        let output = Output::default();
+        let logger = builder("info")
+            .target(Target::Pipe(Box::new(output.clone())))
+            .build();
+        logger.log(record);
+        logger.flush();
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    // A newline in user input can't start a forged entry of its own: the
+    // record stays a single line, with the fake entry inside its message.
+    #[test]
+    fn a_newline_in_input_cannot_forge_a_log_line() {
+        let input = "widget\n[FAKE] admin login";
+        let message = sanitize_for_log(input);
+        let line = written(
+            &Record::builder()
+                .args(format_args!("Search for: {}", message))
+                .level(log::Level::Info)
+                .target(APP_TARGET)
+                .build(),
+        );
+        assert_eq!(line.lines().count(), 1, "{}", line);
+        assert!(!line.lines().any(|l| l.starts_with("[FAKE]")), "{}", line);
+        let json: JsonValue = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["message"], "Search for: widget[FAKE] admin login");
+    }
+
+    // A malformed `RUST_LOG` falls back to the default level (and is handed
+    // back to be warned about); a valid one is used as is.
+    #[test]
+    fn a_bogus_rust_log_falls_back_to_the_default() {
+        let (filter, rejected) = choose_filter(Some("info,=loud,my app"), "warn");
+        assert_eq!((filter, rejected), ("warn", Some("info,=loud,my app")));
+        assert_eq!(builder(filter).build().filter(), LevelFilter::Warn);
+
+        assert_eq!(choose_filter(Some("debug"), "warn"), ("debug", None));
+        assert_eq!(choose_filter(Some("  "), "warn"), ("warn", None));
+        assert_eq!(choose_filter(None, "warn"), ("warn", None));
+    }
+
+    // In JSON mode each line parses on its own, and the structured `detail`
+    // field carries the redacted value `log_sanitized!` gives it.
+    #[test]
+    fn a_json_line_parses_with_its_detail_redacted() {
+        let detail = crate::redaction::sanitize_field_for_log(
+            "connect failed: postgres://admin:supersecret@db:5432/app",
+        );
+        let fields = [("error.variant", "db_error"), ("detail", detail.as_str())];
+        let line = written(
+            &Record::builder()
+                .args(format_args!("SECURE (internal log): Detailed DB Error"))
+                .level(log::Level::Error)
+                .target(APP_TARGET)
+                .key_values(&fields)
+                .build(),
+        );
+        let json: JsonValue = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "error");
+        assert_eq!(json["target"], APP_TARGET);
+        assert_eq!(json["error.variant"], "db_error");
+        assert_eq!(
+            json["detail"],
+            "connect failed: postgres://***:***@db:5432/app"
+        );
+        assert!(!line.contains("supersecret"), "{}", line);
+    }
+}
@@ -0,0 +1,124 @@
+// =========================================================================
+// --- Localized Generic Error Messages ---
+// =========================================================================
+
+// Translations of the generic, client-facing error messages, chosen from the
+// request's `Accept-Language` header. Only these fixed strings are
+// translated; internal details never pass through here.
+
+#[derive(Clone, Copy, Debug)]
+pub enum Language {
+    En,
+    Es,
+    Fr,
+}
+
+impl Language {
+    // The tag sent back in `Content-Language`.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::Es => "es",
+            Language::Fr => "fr",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        // Only the primary subtag matters: `es-MX` is served as `es`.
+        let primary = tag.split('-').next()?.trim();
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::En),
+            "es" => Some(Language::Es),
+            "fr" => Some(Language::Fr),
+            _ => None,
+        }
+    }
+}
+
+// Pick the supported language the client prefers most, e.g.
+// `fr;q=0.5, es` -> Spanish. Unknown or malformed input falls back to English.
+pub fn negotiate(accept_language: &str) -> Language {
+    let mut best: Option<(Language, f32)> = None;
+    for entry in accept_language.split(',') {
+        let mut parts = entry.split(';');
+        let Some(lang) = parts.next().and_then(Language::from_tag) else {
+            continue;
+        };
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((lang, q));
+        }
+    }
+    best.map_or(Language::En, |(lang, _)| lang)
+}
+
+// The translated message for an error code (see `AppError::error_code`).
+// Returns `None` for English or an unknown code, so the caller keeps using
+// `AppError::user_message()`.
+pub fn lookup(lang: Language, code: &str) -> Option<&'static str> {
+    let message = match (lang, code) {
+        (Language::En, _) => return None,
+        (Language::Es, "not_found") => "No se encontró el recurso solicitado.",
+        (Language::Es, "unauthorized") => "Se requiere autenticación para acceder a este recurso.",
+        (Language::Es, "validation_error") => "La solicitud contenía datos no válidos.",
+        (Language::Es, "timeout") => {
+            "La solicitud ha excedido el tiempo de espera. Inténtelo de nuevo más tarde."
+        }
+        (Language::Es, "rate_limited") => "Demasiadas solicitudes. Por favor, vaya más despacio.",
+        (Language::Es, "forbidden") => "No tiene permiso para realizar esta acción.",
+        (Language::Es, "conflict") => {
+            "El recurso ya existe o entra en conflicto con el estado actual."
+        }
+        (Language::Es, "idempotency_key_reused") => {
+            "Esta clave de idempotencia ya se usó con una solicitud diferente."
+        }
+        (Language::Es, "payload_too_large") => "El cuerpo de la solicitud es demasiado grande.",
+        (Language::Es, "bad_gateway") => {
+            "Un servicio del que dependemos no está disponible. Inténtelo de nuevo más tarde."
+        }
+        (Language::Es, "service_unavailable") => {
+            "El servicio no está disponible temporalmente. Inténtelo de nuevo más tarde."
+        }
+        (Language::Es, "unsupported_media_type") => {
+            "El cuerpo de la solicitud tiene un tipo de contenido no admitido."
+        }
+        (Language::Es, "uri_too_long") => "La URI de la solicitud es demasiado larga.",
+        (Language::Es, "dependency_failure") => {
+            "Un servicio del que dependemos no está disponible. Inténtelo de nuevo más tarde."
+        }
+        (Language::Es, _) => "Se produjo un error inesperado. Inténtelo de nuevo más tarde.",
+        (Language::Fr, "not_found") => "La ressource demandée est introuvable.",
+        (Language::Fr, "unauthorized") => {
+            "Une authentification est requise pour accéder à cette ressource."
+        }
+        (Language::Fr, "validation_error") => "La requête contenait des données invalides.",
+        (Language::Fr, "timeout") => "La requête a expiré. Veuillez réessayer plus tard.",
+        (Language::Fr, "rate_limited") => "Trop de requêtes. Veuillez ralentir.",
+        (Language::Fr, "forbidden") => "Vous n'avez pas l'autorisation d'effectuer cette action.",
+        (Language::Fr, "conflict") => {
+            "La ressource existe déjà ou est en conflit avec l'état actuel."
+        }
+        (Language::Fr, "idempotency_key_reused") => {
+            "Cette clé d'idempotence a déjà été utilisée avec une requête différente."
+        }
+        (Language::Fr, "payload_too_large") => "Le corps de la requête est trop volumineux.",
+        (Language::Fr, "bad_gateway") => {
+            "Un service dont nous dépendons est indisponible. Veuillez réessayer plus tard."
+        }
+        (Language::Fr, "service_unavailable") => {
+            "Le service est temporairement indisponible. Veuillez réessayer plus tard."
+        }
+        (Language::Fr, "unsupported_media_type") => {
+            "Le corps de la requête a un type de contenu non pris en charge."
+        }
+        (Language::Fr, "uri_too_long") => "L'URI de la requête est trop longue.",
+        (Language::Fr, "dependency_failure") => {
+            "Un service dont nous dépendons est indisponible. Veuillez réessayer plus tard."
+        }
+        (Language::Fr, _) => "Une erreur inattendue s'est produite. Veuillez réessayer plus tard.",
+    };
+    Some(message)
+}
@@ -0,0 +1,262 @@
+// =========================================================================
+// --- Error Metrics ---
+// =========================================================================
+
+// Counts of errors per `AppError` variant, exposed at `/metrics` in the
+// Prometheus text format. Operators can watch error rates without reading
+// logs that may contain sensitive details. `error_rate_1m` counts all
+// errors in the last minute, for alerting on spikes. Dependency failures
+// are counted per dependency, as `dependency_failure_total{dependency="..."}`.
+
+use actix_web::{HttpResponse, web};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::audit::unix_timestamp;
+use crate::{AppError, DbErrorKind};
+
+// How many seconds `ErrorWindow` looks back.
+const WINDOW_SECS: u64 = 60;
+
+// The low 32 bits of a packed `ErrorWindow` slot.
+const LOW_BITS: u64 = 0xFFFF_FFFF;
+
+// Errors per second over the last minute, in a ring of one slot per second.
+// Each slot packs the second it is counting (high 32 bits) and its count
+// (low 32 bits) into a single atomic, so recording is one lock-free
+// read-modify-write, and a slot can't be reset for a new second while
+// another thread is still counting into it.
+struct ErrorWindow {
+    slots: Box<[AtomicU64]>,
+}
+
+impl Default for ErrorWindow {
+    fn default() -> Self {
+        ErrorWindow {
+            slots: (0..WINDOW_SECS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl ErrorWindow {
+    // Count one error at `now` (seconds since the Unix epoch).
+    fn record_at(&self, now: u64) {
+        let stamp = now & LOW_BITS;
+        let slot = &self.slots[(now % WINDOW_SECS) as usize];
+        let _ = slot.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+            let count = if packed >> 32 == stamp {
+                (packed & LOW_BITS).saturating_add(1).min(LOW_BITS)
+            } else {
+                1
+            };
+            Some((stamp << 32) | count)
+        });
+    }
+
+    // How many errors were counted in the `WINDOW_SECS` seconds up to `now`.
+    fn count_at(&self, now: u64) -> u64 {
+        let stamp = now & LOW_BITS;
+        self.slots
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .filter(|packed| stamp.wrapping_sub(packed >> 32) & LOW_BITS < WINDOW_SECS)
+            .map(|packed| packed & LOW_BITS)
+            .sum()
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    db_error_total: AtomicU64,
+    generic_error_total: AtomicU64,
+    not_found_total: AtomicU64,
+    unauthorized_total: AtomicU64,
+    validation_error_total: AtomicU64,
+    timeout_total: AtomicU64,
+    rate_limited_total: AtomicU64,
+    forbidden_total: AtomicU64,
+    internal_error_total: AtomicU64,
+    conflict_total: AtomicU64,
+    idempotency_key_reused_total: AtomicU64,
+    payload_too_large_total: AtomicU64,
+    transient_db_error_total: AtomicU64,
+    bad_gateway_total: AtomicU64,
+    service_unavailable_total: AtomicU64,
+    unsupported_media_type_total: AtomicU64,
+    uri_too_long_total: AtomicU64,
+    recent: ErrorWindow,
+    // Keyed by `AppError::DependencyFailure::dependency`, a fixed identifier
+    // from our code, so the label set stays small and never holds user input.
+    dependency_failures: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    // Count one occurrence of `error`.
+    pub fn record(&self, error: &AppError) {
+        self.recent.record_at(unix_timestamp());
+        let counter = match error {
+            AppError::DbError {
+                kind: DbErrorKind::Permanent,
+                ..
+            } => &self.db_error_total,
+            AppError::DbError {
+                kind: DbErrorKind::Transient,
+                ..
+            } => &self.transient_db_error_total,
+            AppError::GenericError(_) => &self.generic_error_total,
+            AppError::NotFound(_) => &self.not_found_total,
+            AppError::Unauthorized(_) => &self.unauthorized_total,
+            AppError::Validation(_) => &self.validation_error_total,
+            AppError::Timeout(_) => &self.timeout_total,
+            AppError::RateLimited { .. } => &self.rate_limited_total,
+            AppError::Forbidden(_) => &self.forbidden_total,
+            AppError::Wrapped { .. } => &self.internal_error_total,
+            AppError::Conflict(_) => &self.conflict_total,
+            AppError::IdempotencyKeyReused(_) => &self.idempotency_key_reused_total,
+            AppError::PayloadTooLarge(_) => &self.payload_too_large_total,
+            AppError::BadGateway(_) => &self.bad_gateway_total,
+            AppError::ServiceUnavailable { .. } => &self.service_unavailable_total,
+            AppError::UnsupportedMediaType(_) => &self.unsupported_media_type_total,
+            AppError::UriTooLong(_) => &self.uri_too_long_total,
+            AppError::DependencyFailure { dependency, .. } => {
+                let mut failures = self
+                    .dependency_failures
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                *failures.entry(dependency).or_default() += 1;
+                return;
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Render all counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = [
+            ("db_error_total", &self.db_error_total),
+            ("generic_error_total", &self.generic_error_total),
+            ("not_found_total", &self.not_found_total),
+            ("unauthorized_total", &self.unauthorized_total),
+            ("validation_error_total", &self.validation_error_total),
+            ("timeout_total", &self.timeout_total),
+            ("rate_limited_total", &self.rate_limited_total),
+            ("forbidden_total", &self.forbidden_total),
+            ("internal_error_total", &self.internal_error_total),
+            ("conflict_total", &self.conflict_total),
+            (
+                "idempotency_key_reused_total",
+                &self.idempotency_key_reused_total,
+            ),
+            ("payload_too_large_total", &self.payload_too_large_total),
+            ("transient_db_error_total", &self.transient_db_error_total),
+            ("bad_gateway_total", &self.bad_gateway_total),
+            ("service_unavailable_total", &self.service_unavailable_total),
+            (
+                "unsupported_media_type_total",
+                &self.unsupported_media_type_total,
+            ),
+            ("uri_too_long_total", &self.uri_too_long_total),
+        ];
+
+        let mut output = String::new();
+        for (name, counter) in counters {
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(output, "# TYPE dependency_failure_total counter");
+        let failures = self
+            .dependency_failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        for (dependency, count) in failures.iter() {
+            let _ = writeln!(
+                output,
+                "dependency_failure_total{{dependency=\"{}\"}} {}",
+                dependency, count
+            );
+        }
+        drop(failures);
+        let _ = writeln!(output, "# TYPE error_rate_1m gauge");
+        let _ = writeln!(
+            output,
+            "error_rate_1m {}",
+            self.recent.count_at(unix_timestamp())
+        );
+        output
+    }
+}
+
+// Handler for `GET /metrics`.
+pub async fn metrics_endpoint(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redaction::SensitiveString;
+
+    fn db_error() -> AppError {
+        AppError::DbError {
+            kind: DbErrorKind::Permanent,
+            detail: SensitiveString::from("syntax error".to_string()),
+        }
+    }
+
+    #[test]
+    fn counts_errors_by_variant() {
+        let metrics = Metrics::default();
+        metrics.record(&db_error());
+        metrics.record(&db_error());
+        metrics.record(&AppError::NotFound("row".to_string()));
+
+        let output = metrics.render();
+        assert!(output.contains("\ndb_error_total 2\n"), "{}", output);
+        assert!(output.contains("\nnot_found_total 1\n"), "{}", output);
+        assert!(output.contains("\ngeneric_error_total 0\n"), "{}", output);
+        assert!(output.contains("\nerror_rate_1m 3\n"), "{}", output);
+    }
+
+    // The window counts the last 60 seconds only: older errors drop out,
+    // including ones whose slot is reused for a newer second.
+    #[test]
+    fn the_error_window_counts_recent_errors_only() {
+        let window = ErrorWindow::default();
+        let start = 1_700_000_000;
+        for _ in 0..3 {
+            window.record_at(start);
+        }
+        window.record_at(start + 30);
+        assert_eq!(window.count_at(start + 30), 4);
+        assert_eq!(window.count_at(start + 59), 4);
+        assert_eq!(window.count_at(start + 60), 1);
+        assert_eq!(window.count_at(start + 90), 0);
+
+        // Same slot as `start`, a minute later: counted afresh
+        window.record_at(start + 60);
+        assert_eq!(window.count_at(start + 60), 2);
+    }
+
+    #[test]
+    fn counts_dependency_failures_by_dependency() {
+        let metrics = Metrics::default();
+        for _ in 0..2 {
+            metrics.record(&AppError::DependencyFailure {
+                dependency: "inventory_service",
+                detail: "connection refused".to_string(),
+            });
+        }
+
+        let output = metrics.render();
+        assert!(
+            output.contains("\ndependency_failure_total{dependency=\"inventory_service\"} 2\n"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("connection refused"), "{}", output);
+    }
+}
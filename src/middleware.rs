@@ -0,0 +1,637 @@
+// =========================================================================
+// --- Middleware ---
+// =========================================================================
+
+use actix_web::{
+    Error, HttpMessage, HttpResponse, ResponseError,
+    body::{self, BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::JsonPayloadError,
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    web::{self, Bytes},
+};
+use futures_util::FutureExt;
+use log::{error, info, warn};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::AppError;
+use crate::config::AppConfig;
+use crate::redaction::{
+    RedactionOptions, SecretScrubber, redact_secrets, redact_secrets_with, sanitize_detail_for_log,
+    sanitize_for_log,
+};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// The id of the current request, stored in the request extensions.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// Only accept incoming ids that are short and made of harmless characters,
+// so a client can't use the header to inject text into our logs.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Middleware: reuse the incoming `X-Request-Id` (or generate one), store it
+// in the request extensions for logging, and echo it back on the response.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    // Handler errors have already been rendered into a response by now,
+    // so this covers both the success and the error path.
+    let mut res = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+// Middleware: log each request's method, path and headers. Values of the
+// headers in `AppConfig::redacted_headers` are replaced with `***`; the rest
+// are redacted and sanitized like any other user input. The query string is
+// left out, since it can carry credentials too.
+pub async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let config = req.app_data::<web::Data<AppConfig>>().cloned();
+    let headers = req
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = if config
+                .as_ref()
+                .is_some_and(|c| c.redacted_headers.contains(name.as_str()))
+            {
+                "***".to_string()
+            } else {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                let options = config.as_ref().map(|c| c.redaction()).unwrap_or_default();
+                sanitize_for_log(&redact_secrets_with(&value, options))
+            };
+            format!("{}={}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    info!(
+        request_id = request_id.as_deref().unwrap_or("-"),
+        method = req.method().as_str(),
+        path = sanitize_for_log(req.path()).as_str(),
+        headers = headers.as_str();
+        "Request received"
+    );
+    next.call(req).await
+}
+
+// Middleware: warn about requests whose response took longer than
+// `AppConfig::slow_request_threshold` to produce, with the method, path and
+// duration (never the query string). Only measured up to the response head,
+// so a long streamed body doesn't count.
+pub async fn log_slow_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let threshold = req
+        .app_data::<web::Data<AppConfig>>()
+        .map(|config| config.slow_request_threshold);
+    let method = req.method().clone();
+    let path = sanitize_for_log(req.path());
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let started = Instant::now();
+
+    let res = next.call(req).await;
+
+    let elapsed = started.elapsed();
+    if threshold.is_some_and(|threshold| elapsed > threshold) {
+        warn!(
+            request_id = request_id.as_deref().unwrap_or("-"),
+            method = method.as_str(),
+            path = path.as_str(),
+            duration_ms = elapsed.as_millis();
+            "Slow request: took {:?}",
+            elapsed
+        );
+    }
+    res
+}
+
+// Middleware: with the `tracing` feature, run the rest of the request in a
+// `request` span holding the method, path and request id (never the query
+// string, which can carry user data). The status is recorded when the
+// response is ready, and errors add their code (see `AppError::record`).
+// Without the feature this does nothing.
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::{Instrument, field};
+
+        let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+        let span = tracing::info_span!(
+            "request",
+            method = req.method().as_str(),
+            path = sanitize_for_log(req.path()).as_str(),
+            request_id = request_id.as_deref().unwrap_or("-"),
+            status = field::Empty,
+            error = field::Empty,
+        );
+        let res = next.call(req).instrument(span.clone()).await?;
+        span.record("status", res.status().as_u16());
+        Ok(res)
+    }
+    #[cfg(not(feature = "tracing"))]
+    next.call(req).await
+}
+
+// A restrictive policy for our pages: they load nothing and can't be framed.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; frame-ancestors 'none'; base-uri 'none'";
+
+// Middleware: attach security headers to every response, including the
+// HTML error pages rendered from `AppError`, to reduce XSS/clickjacking risk.
+pub async fn security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+    apply_security_headers(res.headers_mut());
+    Ok(res)
+}
+
+// The security headers themselves, shared with `ErrorResponseBuilder` so an
+// error response carries them even if it never passes this middleware.
+pub fn apply_security_headers(headers: &mut HeaderMap) {
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+    );
+}
+
+pub const DEPRECATION_HEADER: HeaderName = HeaderName::from_static("deprecation");
+pub const SUNSET_HEADER: HeaderName = HeaderName::from_static("sunset");
+
+// Middleware: mark responses on `AppConfig::deprecated_paths` with
+// `Deprecation: true` and, if configured, `Sunset`. Wrapped outside every
+// middleware that renders errors, so error responses are marked too.
+pub async fn deprecation(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let config = req.app_data::<web::Data<AppConfig>>().cloned();
+    let deprecated = config.as_ref().is_some_and(|config| {
+        config.deprecated_paths.iter().any(|path| {
+            req.path() == path
+                || req
+                    .path()
+                    .strip_prefix(path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    });
+    let mut res = next.call(req).await?;
+    if deprecated {
+        let headers = res.headers_mut();
+        headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+        let sunset = config.as_ref().and_then(|config| config.sunset.as_deref());
+        if let Some(value) = sunset.and_then(|sunset| HeaderValue::from_str(sunset).ok()) {
+            headers.insert(SUNSET_HEADER, value);
+        }
+    }
+    Ok(res)
+}
+
+// Paths still served in maintenance mode, so load balancers and probes can
+// tell "down for maintenance" from "down".
+const MAINTENANCE_EXEMPT_PATHS: &[&str] = &["/health"];
+
+// What clients are told to wait before retrying during maintenance.
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 300;
+
+// Middleware: with `AppConfig::maintenance_mode` on, answer every request
+// outside `MAINTENANCE_EXEMPT_PATHS` with `AppError::ServiceUnavailable`
+// (503 and `Retry-After`) without reaching a handler. Must run inside
+// `request_context`, so the error is rendered like any other.
+pub async fn maintenance_mode(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let enabled = req
+        .app_data::<web::Data<AppConfig>>()
+        .is_some_and(|config| config.maintenance_mode);
+    if enabled && !MAINTENANCE_EXEMPT_PATHS.contains(&req.path()) {
+        let e = AppError::ServiceUnavailable {
+            detail: "maintenance mode is active".to_string(),
+            retry_after_secs: MAINTENANCE_RETRY_AFTER_SECS,
+        };
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+// Middleware for the JSON endpoints: reject a request whose declared
+// `Content-Length` is over `AppConfig::max_body_bytes` with
+// `AppError::PayloadTooLarge` before a single byte of the body is read.
+// Bodies without a length (chunked) or that lie about it are still cut off
+// by the `JsonConfig` limit while reading.
+pub async fn reject_oversized_body(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limit = req
+        .app_data::<web::Data<AppConfig>>()
+        .map(|config| config.max_body_bytes);
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    if let (Some(limit), Some(length)) = (limit, declared)
+        && length > limit as u64
+    {
+        let e = AppError::PayloadTooLarge(format!(
+            "declared Content-Length {} exceeds limit of {} bytes",
+            length, limit
+        ));
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+// How much of an over-long URI is kept in the log detail.
+const LOGGED_URI_PREFIX_BYTES: usize = 64;
+
+// Middleware: reject requests whose URI (path plus query string) is longer
+// than `AppConfig::max_uri_len` with `AppError::UriTooLong` (414). Huge
+// query strings are a cheap way to flood logs, so only the length and the
+// first few bytes of the URI are logged. Must run inside `request_context`.
+pub async fn limit_uri_length(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limit = req
+        .app_data::<web::Data<AppConfig>>()
+        .map(|config| config.max_uri_len);
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map_or(req.path(), |path_and_query| path_and_query.as_str());
+    if let Some(limit) = limit
+        && uri.len() > limit
+    {
+        let prefix = sanitize_detail_for_log(&redact_secrets(uri), LOGGED_URI_PREFIX_BYTES);
+        let e = AppError::UriTooLong(format!(
+            "URI of {} bytes exceeds limit of {} bytes: {}",
+            uri.len(),
+            limit,
+            prefix
+        ));
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+// Middleware: if a handler's `web::Json` response fails to serialize, actix
+// answers with its own error, whose message names the failing type or field.
+// Log that message (redacted) with the request id and answer with the
+// standard `AppError::GenericError` response instead. Must run inside
+// `request_context`, so the error is rendered like any other.
+pub async fn mask_serialization_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let res = next.call(req).await?;
+    let failure = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<JsonPayloadError>())
+        .and_then(|e| match e {
+            JsonPayloadError::Serialize(e) => Some(e.to_string()),
+            _ => None,
+        });
+    let Some(message) = failure else {
+        return Ok(res.map_into_left_body());
+    };
+    let request_id = res
+        .request()
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone());
+    error!(
+        request_id = request_id.as_deref().unwrap_or("-"),
+        detail = sanitize_for_log(&redact_secrets(&message)).as_str();
+        "SECURE (internal log): Failed to serialize response body"
+    );
+    let (http_req, _) = res.into_parts();
+    let res = AppError::GenericError(None).error_response();
+    Ok(ServiceResponse::new(http_req, res).map_into_right_body())
+}
+
+// Tell browsers to use HTTPS only, for a year, on all subdomains.
+const STRICT_TRANSPORT_SECURITY: &str = "max-age=31536000; includeSubDomains";
+
+// Middleware: with `AppConfig::enforce_https` on, permanently redirect
+// requests that arrived over plain HTTP to the same path on
+// `AppConfig::canonical_host` over HTTPS, and add `Strict-Transport-Security`
+// to HTTPS responses. The scheme comes from the `Forwarded`/
+// `X-Forwarded-Proto` header only when the peer is one of
+// `AppConfig::trusted_proxies`; anyone else could just claim HTTPS. The
+// redirect never uses the client's `Host`, which would make it an open
+// redirect; without a canonical host, plain HTTP is refused.
+pub async fn enforce_https(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let Some(config) = req
+        .app_data::<web::Data<AppConfig>>()
+        .filter(|config| config.enforce_https)
+        .cloned()
+    else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let from_trusted_proxy = req
+        .peer_addr()
+        .is_some_and(|addr| config.trusted_proxies.contains(&addr.ip()));
+    let is_https = if from_trusted_proxy {
+        req.connection_info().scheme() == "https"
+    } else {
+        req.app_config().secure()
+    };
+    if !is_https {
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        let res = match &config.canonical_host {
+            Some(host) => match HeaderValue::from_str(&format!("https://{}{}", host, path)) {
+                Ok(location) => HttpResponse::PermanentRedirect()
+                    .insert_header((header::LOCATION, location))
+                    .finish(),
+                Err(_) => AppError::GenericError(None).error_response(),
+            },
+            None => AppError::Forbidden(
+                "plain HTTP refused: no CANONICAL_HOST to redirect to".to_string(),
+            )
+            .error_response(),
+        };
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+
+    let mut res = next.call(req).await?;
+    res.headers_mut().insert(
+        header::STRICT_TRANSPORT_SECURITY,
+        HeaderValue::from_static(STRICT_TRANSPORT_SECURITY),
+    );
+    Ok(res.map_into_left_body())
+}
+
+// The number of requests currently being handled, shared via `web::Data`
+// so `main` can report how many are still draining at shutdown.
+#[derive(Default)]
+pub struct InFlight(AtomicUsize);
+
+impl InFlight {
+    pub fn current(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Decrements the counter when dropped, so a request that errors, panics or
+// is cancelled mid-flight is still counted as finished.
+struct InFlightGuard(web::Data<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Middleware: count the request as in flight until its response is ready.
+pub async fn track_in_flight(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let _guard = req
+        .app_data::<web::Data<InFlight>>()
+        .cloned()
+        .map(|in_flight| {
+            in_flight.0.fetch_add(1, Ordering::Relaxed);
+            InFlightGuard(in_flight)
+        });
+    next.call(req).await
+}
+
+// Bodies bigger than this are passed through without being scrubbed, since
+// buffering them would cost too much memory.
+const MAX_SCRUBBED_BODY_BYTES: usize = 1024 * 1024;
+
+// Left alone on purpose: the vulnerable demo endpoint must keep leaking.
+const UNSCRUBBED_PATHS: &[&str] = &["/vulnerable-search"];
+
+// Only text responses are scrubbed; binary bodies can't contain our secrets
+// as text and rewriting them would corrupt them. Bodies without a
+// `Content-Type` (e.g. a plain `.body(String)`) are scrubbed if they're UTF-8.
+fn is_scrubbable(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE) else {
+        return true;
+    };
+    content_type.to_str().ok().is_some_and(|content_type| {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        media_type.starts_with("text/")
+            || media_type == "application/json"
+            || media_type.ends_with("+json")
+    })
+}
+
+// Mask secrets in one piece of response text, noting it in the log if any
+// were found.
+fn scrub_text(
+    text: &str,
+    options: RedactionOptions,
+    scrubber: Option<&web::Data<SecretScrubber>>,
+) -> String {
+    let mut scrubbed = redact_secrets_with(text, options);
+    if let Some(scrubber) = scrubber {
+        scrubbed = scrubber.scrub(&scrubbed);
+    }
+    if scrubbed != text {
+        warn!("SECURE (internal log): Redacted a secret from a response body");
+    }
+    scrubbed
+}
+
+// A streaming body, scrubbed chunk by chunk as it is sent. A secret split
+// across two chunks isn't caught, but streams are never held in memory.
+struct ScrubbedChunks {
+    body: BoxBody,
+    options: RedactionOptions,
+    scrubber: Option<web::Data<SecretScrubber>>,
+}
+
+impl MessageBody for ScrubbedChunks {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = &mut *self;
+        Pin::new(&mut this.body).poll_next(cx).map(|chunk| {
+            chunk.map(|chunk| {
+                chunk.map(|bytes| match std::str::from_utf8(&bytes) {
+                    Ok(text) => Bytes::from(scrub_text(text, this.options, this.scrubber.as_ref())),
+                    Err(_) => bytes,
+                })
+            })
+        })
+    }
+}
+
+// Middleware (defense in depth): run `redact_secrets` and the configured
+// `SecretScrubber` over every text/JSON response body before it leaves the
+// server, in case a handler ever writes a secret into a response directly.
+// Sized bodies are buffered (up to `MAX_SCRUBBED_BODY_BYTES`) and scrubbed
+// whole; streaming bodies are scrubbed chunk by chunk.
+pub async fn scrub_response_body(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if UNSCRUBBED_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_boxed_body);
+    }
+    // Outside `request_context`, so the options come from the app itself
+    let options = req
+        .app_data::<web::Data<AppConfig>>()
+        .map(|config| config.redaction())
+        .unwrap_or_default();
+    let scrubber = req.app_data::<web::Data<SecretScrubber>>().cloned();
+    let res = next.call(req).await?;
+
+    if !is_scrubbable(res.headers()) {
+        return Ok(res.map_into_boxed_body());
+    }
+    if let BodySize::Sized(size) = res.response().body().size()
+        && size > MAX_SCRUBBED_BODY_BYTES as u64
+    {
+        warn!(
+            "SECURE (internal log): Response body of {} bytes too large to scrub",
+            size
+        );
+        return Ok(res.map_into_boxed_body());
+    }
+    if let BodySize::Stream = res.response().body().size() {
+        return Ok(res.map_body(|_, body| {
+            BoxBody::new(ScrubbedChunks {
+                body: body.boxed(),
+                options,
+                scrubber,
+            })
+        }));
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = match body::to_bytes_limited(body, MAX_SCRUBBED_BODY_BYTES).await {
+        Ok(Ok(bytes)) => bytes,
+        // The body is gone at this point, so answer with a generic error
+        // rather than sending half of it unscrubbed.
+        Ok(Err(_)) | Err(_) => {
+            warn!("SECURE (internal log): Could not buffer response body for scrubbing");
+            return Ok(ServiceResponse::new(
+                req,
+                AppError::GenericError(None).error_response(),
+            ));
+        }
+    };
+
+    let body = match std::str::from_utf8(&bytes) {
+        Ok(text) => BoxBody::new(scrub_text(text, options, scrubber.as_ref())),
+        Err(_) => BoxBody::new(bytes),
+    };
+    Ok(ServiceResponse::new(req, res.set_body(body)))
+}
+
+// The message a panic was raised with, if it's a string.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+// Resource middleware: if the handler panics, log the (redacted) panic
+// message with the request id and answer with the standard
+// `AppError::GenericError` response instead of dropping the connection.
+// Panic messages often carry debug data, so they never reach the client.
+//
+// This has to wrap each resource rather than the whole app: it keeps a copy
+// of the `HttpRequest` to build the response with, and holding one while
+// the app is still routing the request makes actix panic.
+pub async fn catch_panic(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let http_req = req.request().clone();
+    match AssertUnwindSafe(next.call(req)).catch_unwind().await {
+        Ok(res) => res.map(ServiceResponse::map_into_left_body),
+        Err(payload) => {
+            let request_id = http_req
+                .extensions()
+                .get::<RequestId>()
+                .map(|id| id.0.clone());
+            error!(
+                request_id = request_id.as_deref().unwrap_or("-"),
+                detail = sanitize_for_log(&redact_secrets(panic_message(&*payload))).as_str();
+                "SECURE (internal log): Handler panicked"
+            );
+            let res = AppError::GenericError(None).error_response();
+            Ok(ServiceResponse::new(http_req, res).map_into_right_body())
+        }
+    }
+}
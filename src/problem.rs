@@ -0,0 +1,115 @@
+// =========================================================================
+// --- RFC 7807 Problem Details ---
+// =========================================================================
+
+// A `application/problem+json` body as described in RFC 7807.
+// `detail` is always one of our generic, sanitized messages, never the
+// internal error contents.
+
+use log::error;
+use serde::Serialize;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+#[derive(Debug, Serialize)]
+pub struct ProblemDetails {
+    // A URI reference identifying the problem type.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    // A short summary of the problem type (the HTTP reason phrase).
+    pub title: String,
+    // The HTTP status code, which always matches the response status.
+    pub status: u16,
+    // A human-readable, generic explanation safe to show to clients.
+    pub detail: String,
+    // Extension member: the correlation id also written to the server log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    // Extension member: the request's `X-Request-Id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    // Extension member: the internal detail, only in dev mode for
+    // client-safe errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_detail: Option<String>,
+}
+
+// Serialize a problem details body. Should that ever fail (a broken
+// `Serialize` impl, say), the client gets a minimal, hardcoded problem for
+// the same status instead of the serializer's message or actix's default
+// error page. Generic so any body type goes through the same fallback.
+pub fn to_json_or_fallback(problem: &impl Serialize, status: u16) -> String {
+    match serde_json::to_string(problem) {
+        Ok(json) => json,
+        Err(e) => {
+            // Only the category: the message could quote the value itself.
+            error!(
+                "Failed to serialize problem details ({:?} error); sent the fallback body",
+                e.classify()
+            );
+            fallback_json(status)
+        }
+    }
+}
+
+// The minimal problem body, built without serde so it can't fail.
+fn fallback_json(status: u16) -> String {
+    format!(
+        r#"{{"type":"about:blank","title":"Error","status":{},"detail":"An unexpected error occurred."}}"#,
+        status
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A body whose `Serialize` impl always fails, with a message that
+    // mustn't reach the client.
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom(
+                "cannot serialize postgres://admin:supersecret@db",
+            ))
+        }
+    }
+
+    #[test]
+    fn a_failed_serialization_sends_the_fallback() {
+        let json = to_json_or_fallback(&Unserializable, 500);
+        assert_eq!(
+            json,
+            r#"{"type":"about:blank","title":"Error","status":500,"detail":"An unexpected error occurred."}"#
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["status"], 500);
+    }
+
+    #[test]
+    fn a_problem_serializes_without_empty_extensions() {
+        let problem = ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: "Not Found".to_string(),
+            status: 404,
+            detail: "The requested resource was not found.".to_string(),
+            reference: None,
+            request_id: None,
+            debug_detail: None,
+        };
+        assert_eq!(
+            to_json_or_fallback(&problem, 404),
+            r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"The requested resource was not found."}"#
+        );
+    }
+
+    // The body's `status` is always the response's.
+    #[test]
+    fn every_variant_has_its_status_in_the_problem() {
+        use actix_web::ResponseError;
+        for e in crate::tests::every_variant() {
+            assert_eq!(e.to_problem().status, e.status_code().as_u16(), "{:?}", e);
+        }
+    }
+}
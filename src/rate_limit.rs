@@ -0,0 +1,108 @@
+// =========================================================================
+// --- In-Memory Rate Limiting ---
+// =========================================================================
+
+// A token bucket per client IP, shared by all workers via `web::Data`.
+// Each request takes one token (a batch, one per item); tokens refill at a steady rate up to the
+// bucket capacity. This slows down enumeration of the search endpoint.
+
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::AppError;
+
+// Stop tracking idle clients once this many buckets exist.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    // `capacity` requests may burst at once; after that, `refill_per_sec`
+    // requests per second are allowed.
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        RateLimiter {
+            capacity: f64::from(capacity),
+            refill_per_sec,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take a token for `ip`, or return `AppError::RateLimited` if none are left.
+    pub fn check(&self, ip: IpAddr) -> Result<(), AppError> {
+        self.check_n(ip, 1)
+    }
+
+    // Take `n` tokens for `ip` at once, or none (and return
+    // `AppError::RateLimited`) if fewer than `n` are left.
+    pub fn check_n(&self, ip: IpAddr, n: u32) -> Result<(), AppError> {
+        let cost = f64::from(n);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // Forget clients whose bucket would be full again anyway.
+            let (capacity, rate) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.last_refill).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+
+        let retry_after_secs = ((cost - bucket.tokens) / self.refill_per_sec).ceil() as u64;
+        Err(AppError::RateLimited {
+            detail: format!(
+                "client {} exceeded rate limit (needed {}, tokens {:.2}/{}, refill {}/s)",
+                ip, n, bucket.tokens, self.capacity, self.refill_per_sec
+            ),
+            retry_after_secs: retry_after_secs.max(1),
+        })
+    }
+}
+
+// Route middleware: apply the shared `RateLimiter` (if registered) to the
+// client's IP. Rejections are rendered through `AppError` like any other error.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let (Some(limiter), Some(ip)) = (limiter, ip)
+        && let Err(e) = limiter.check(ip)
+    {
+        return Ok(req.error_response(e).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,51 @@
+// =========================================================================
+// --- External Error Reporting Hook ---
+// =========================================================================
+
+// Lets users ship error events to Sentry/Datadog/etc. without editing the
+// crate: register a callback in `web::Data<ErrorReporter>` and
+// `error_response()` calls it once per error.
+//
+// The callback gets an `ErrorEvent`, not the `AppError` itself, so it can
+// only ever see the redacted detail that also goes to our own log.
+
+use actix_web::http::StatusCode;
+
+// A sanitized description of one error response.
+#[allow(dead_code)] // Read by user-registered reporters.
+pub struct ErrorEvent<'a> {
+    // The correlation id shown to the client
+    pub reference: &'a str,
+    pub request_id: Option<&'a str>,
+    // Machine-readable variant code, e.g. `db_error` or (internal only)
+    // `transient_db_error`
+    pub code: &'static str,
+    pub status: StatusCode,
+    // The internal detail, already redacted
+    pub detail: &'a str,
+}
+
+type Callback = Box<dyn Fn(&ErrorEvent<'_>) + Send + Sync>;
+
+pub struct ErrorReporter {
+    callback: Callback,
+}
+
+impl ErrorReporter {
+    pub fn new(callback: impl Fn(&ErrorEvent<'_>) + Send + Sync + 'static) -> Self {
+        ErrorReporter {
+            callback: Box::new(callback),
+        }
+    }
+
+    pub fn report(&self, event: &ErrorEvent<'_>) {
+        (self.callback)(event)
+    }
+}
+
+// The default reporter does nothing.
+impl Default for ErrorReporter {
+    fn default() -> Self {
+        ErrorReporter::new(|_| {})
+    }
+}
@@ -0,0 +1,171 @@
+// =========================================================================
+// --- Product Storage Backends ---
+// =========================================================================
+
+// Where search results come from. Handlers only see `ProductRepository`
+// (shared as `web::Data<dyn ProductRepository>`), so the fabricated
+// database, canned test data and a real Postgres table are interchangeable.
+// Every backend reports failures as `AppError`; the details stay internal
+// like any other error.
+
+use actix_web::web;
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::sync::Arc;
+
+use crate::{AppError, ErrorSource, Page};
+
+// Product rows as a backend reads them. An error can arrive after some
+// rows, when part of a response may already have been sent.
+pub type RowStream = BoxStream<'static, Result<String, AppError>>;
+
+#[async_trait]
+pub trait ProductRepository: Send + Sync + 'static {
+    // The requested `page` of product rows matching `query`, possibly none.
+    // Backends apply the page themselves (in SQL for a real database), so
+    // no more rows than one page are ever loaded. Retries, timeouts and the
+    // circuit breaker are applied by the caller.
+    async fn find(&self, query: &str, page: Page) -> Result<Vec<String>, AppError>;
+
+    // The same rows as `find`, as they are read, for pages big enough to
+    // send in chunks (see `streaming`). Backends that can read row by row
+    // override this; by default it's `find`'s result, streamed.
+    fn stream(self: Arc<Self>, query: String, page: Page) -> RowStream {
+        find_as_stream(self, query, page)
+    }
+}
+
+// `find`, as a stream: its rows, or its error as the only item.
+fn find_as_stream<R: ProductRepository + ?Sized>(
+    repository: Arc<R>,
+    query: String,
+    page: Page,
+) -> RowStream {
+    stream::once(async move { repository.find(&query, page).await })
+        .flat_map(|result| match result {
+            Ok(rows) => stream::iter(rows.into_iter().map(Ok)).left_stream(),
+            Err(e) => stream::once(std::future::ready(Err(e))).right_stream(),
+        })
+        .boxed()
+}
+
+// The simulated database from `simulated_rows`, the default backend. Its
+// "queries" block (some deliberately sleep), so they run on actix's thread pool.
+pub struct SimulatedRepository;
+
+#[async_trait]
+impl ProductRepository for SimulatedRepository {
+    async fn find(&self, query: &str, page: Page) -> Result<Vec<String>, AppError> {
+        let input = query.to_string();
+        web::block(move || crate::simulated_rows(&input).map(|rows| page.apply(rows)))
+            .await
+            // The blocking task itself failed (e.g. it panicked)
+            .map_err(|e| AppError::Wrapped {
+                context: "blocking database task failed".to_string(),
+                source: ErrorSource::new(e),
+            })?
+    }
+
+    fn stream(self: Arc<Self>, query: String, page: Page) -> RowStream {
+        match crate::simulated_row_stream(&query) {
+            Some(rows) => stream::iter(rows)
+                .skip(page.skip())
+                .take(page.per_page)
+                .boxed(),
+            None => find_as_stream(self, query, page),
+        }
+    }
+}
+
+// Canned rows for handler tests: a search returns every row containing the
+// query (case-insensitively), and never fails.
+#[cfg(any(test, feature = "test-utils"))]
+#[cfg_attr(not(test), allow(dead_code))] // Used by handler tests only.
+pub struct InMemoryRepository {
+    rows: Vec<String>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[cfg_attr(not(test), allow(dead_code))]
+impl InMemoryRepository {
+    pub fn new(rows: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        InMemoryRepository {
+            rows: rows.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[async_trait]
+impl ProductRepository for InMemoryRepository {
+    async fn find(&self, query: &str, page: Page) -> Result<Vec<String>, AppError> {
+        let needle = query.to_lowercase();
+        Ok(page.apply(
+            self.rows
+                .iter()
+                .filter(|row| row.to_lowercase().contains(&needle))
+                .cloned(),
+        ))
+    }
+}
+
+// The `products` table of a real Postgres database (`--features sqlx` with
+// `DATABASE_URL` set). Driver errors are converted by `From<sqlx::Error>`.
+#[cfg(feature = "sqlx")]
+pub struct SqlxRepository {
+    pool: sqlx::PgPool,
+}
+
+#[cfg(feature = "sqlx")]
+impl SqlxRepository {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        SqlxRepository { pool }
+    }
+}
+
+#[cfg(feature = "sqlx")]
+const SEARCH_SQL: &str =
+    "SELECT name FROM products WHERE name = $1 ORDER BY name LIMIT $2 OFFSET $3";
+
+// How many rows a streaming query reads ahead of the response.
+#[cfg(feature = "sqlx")]
+const STREAM_BUFFER_ROWS: usize = 64;
+
+#[cfg(feature = "sqlx")]
+#[async_trait]
+impl ProductRepository for SqlxRepository {
+    async fn find(&self, query: &str, page: Page) -> Result<Vec<String>, AppError> {
+        let rows = sqlx::query_scalar(SEARCH_SQL)
+            .bind(query)
+            .bind(page.limit())
+            .bind(page.offset())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    // `fetch` borrows the pool and the query, so it runs in a task of its
+    // own and hands rows over a channel as they arrive. The task stops at
+    // the first error, or once the response stops reading.
+    fn stream(self: Arc<Self>, query: String, page: Page) -> RowStream {
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER_ROWS);
+        actix_web::rt::spawn(async move {
+            let mut rows = sqlx::query_scalar::<_, String>(SEARCH_SQL)
+                .bind(&query)
+                .bind(page.limit())
+                .bind(page.offset())
+                .fetch(&self.pool);
+            while let Some(row) = rows.next().await {
+                let failed = row.is_err();
+                if tx.send(row.map_err(AppError::from)).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        stream::unfold(
+            rx,
+            |mut rx| async move { rx.recv().await.map(|row| (row, rx)) },
+        )
+        .boxed()
+    }
+}
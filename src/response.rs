@@ -0,0 +1,223 @@
+// =========================================================================
+// --- Error Response Builder ---
+// =========================================================================
+
+// Every `AppError` response is built here, so the headers every error needs
+// (security and cache headers, the correlation ids, the content type) are
+// applied in one place. A new variant only chooses a status and a safe body; it can't
+// forget a header or hand-roll a body from its internal detail.
+
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    body::BoxBody,
+    http::{
+        StatusCode,
+        header::{self, HeaderName, HeaderValue},
+    },
+};
+use log::error;
+use serde::Serialize;
+
+use crate::AppError;
+use crate::context::with_current;
+use crate::middleware::{REQUEST_ID_HEADER, apply_security_headers};
+use crate::problem;
+
+// The correlation id for this error, also written to the server log.
+pub const ERROR_REFERENCE_HEADER: HeaderName = HeaderName::from_static("x-error-reference");
+
+pub struct ErrorResponseBuilder {
+    status: StatusCode,
+    reference: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ErrorResponseBuilder {
+    pub fn new(status: StatusCode, reference: &str) -> Self {
+        ErrorResponseBuilder {
+            status,
+            reference: reference.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    // An extra header for this response, such as `Retry-After`.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    // A JSON body. Should serializing somehow fail, the client gets the bare
+    // status rather than the serializer's error message.
+    pub fn json(self, content_type: &str, body: &impl Serialize) -> HttpResponse {
+        match serde_json::to_string(body) {
+            Ok(json) => self.finish(content_type, json),
+            Err(_) => self.finish("text/plain; charset=utf-8", String::new()),
+        }
+    }
+
+    // An RFC 7807 problem details body, with a hardcoded minimal problem
+    // as the fallback should serializing fail.
+    pub fn problem(self, body: &impl Serialize) -> HttpResponse {
+        let json = problem::to_json_or_fallback(body, self.status.as_u16());
+        self.finish(problem::CONTENT_TYPE, json)
+    }
+
+    // An HTML page, already rendered from the error page template.
+    pub fn html(self, page: String) -> HttpResponse {
+        self.finish("text/html; charset=utf-8", page)
+    }
+
+    // A plain-text body, for clients that asked for `text/plain`.
+    pub fn text(self, body: String) -> HttpResponse {
+        self.finish("text/plain; charset=utf-8", body)
+    }
+
+    fn finish(self, content_type: &str, body: String) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        for (name, value) in self.headers {
+            response.insert_header((name, value));
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.reference) {
+            response.insert_header((ERROR_REFERENCE_HEADER, value));
+        }
+        let request_id = with_current(|ctx| ctx.request_id.clone()).flatten();
+        if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+            response.insert_header((REQUEST_ID_HEADER, value));
+        }
+        response.insert_header((header::CONTENT_TYPE, content_type));
+        // Errors carry a per-request reference, so no cache (browser or
+        // proxy) may keep one and hand it to someone else.
+        response.insert_header((header::CACHE_CONTROL, "no-store"));
+        response.insert_header((header::PRAGMA, "no-cache"));
+
+        let mut response = response.body(body);
+        apply_security_headers(response.headers_mut());
+        response
+    }
+}
+
+// =========================================================================
+// --- Safe Handler Responses ---
+// =========================================================================
+
+// What a JSON handler returns instead of `impl Responder`: either the value,
+// serialized as a 200 JSON body, or an `AppError`, rendered by the secure
+// error pipeline. There is no third way, so a handler can't hand-roll an
+// error body. Build it from a `Result`, which keeps `?` usable:
+//
+//     SafeResponse::from(async { ...; Ok(value) }.await)
+pub struct SafeResponse<T>(Result<T, AppError>);
+
+impl<T> From<Result<T, AppError>> for SafeResponse<T> {
+    fn from(result: Result<T, AppError>) -> Self {
+        SafeResponse(result)
+    }
+}
+
+impl<T: Serialize> Responder for SafeResponse<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let error = match self.0 {
+            Ok(value) => match serde_json::to_string(&value) {
+                Ok(json) => {
+                    return HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(json);
+                }
+                Err(e) => {
+                    // Only the category: the message could quote the value.
+                    error!(
+                        "SECURE (internal log): Could not serialize a response ({:?} error)",
+                        e.classify()
+                    );
+                    AppError::GenericError(None)
+                }
+            },
+            Err(e) => e,
+        };
+        // Keeps the error attached to the response, for middleware that
+        // inspect it, as if the handler had returned `Err`.
+        HttpResponse::from_error(error)
+    }
+}
+
+// A successful HTML (or streamed) response, for handlers that don't return
+// JSON: `SafeResponse<Html>`. Only success statuses get through; anything
+// else would be a hand-rolled error page, so it's replaced with the generic
+// 500 and logged.
+pub struct Html(pub HttpResponse);
+
+impl Responder for SafeResponse<Html> {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let error = match self.0 {
+            Ok(Html(response)) if response.status().is_success() => return response,
+            Ok(Html(response)) => {
+                error!(
+                    "SECURE (internal log): Handler built a {} response by hand",
+                    response.status()
+                );
+                AppError::GenericError(None)
+            }
+            Err(e) => e,
+        };
+        HttpResponse::from_error(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body, http::StatusCode, test::TestRequest};
+
+    async fn respond(response: SafeResponse<Html>) -> (StatusCode, String) {
+        let res = response.respond_to(&TestRequest::default().to_http_request());
+        let status = res.status();
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[actix_web::test]
+    async fn html_pages_pass_through() {
+        let page = HttpResponse::Ok().body("<h1>Search Result</h1>");
+        let (status, body) = respond(SafeResponse(Ok(Html(page)))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "<h1>Search Result</h1>");
+    }
+
+    // A hand-built error page is replaced, body and all.
+    #[actix_web::test]
+    async fn hand_built_error_pages_become_the_generic_500() {
+        let page = HttpResponse::BadRequest()
+            .body("syntax error near \"x\": postgres://admin:supersecret@db");
+        let (status, body) = respond(SafeResponse(Ok(Html(page)))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body.contains("supersecret"), "{}", body);
+        assert!(!body.contains("syntax error"), "{}", body);
+    }
+
+    #[test]
+    fn the_builder_sets_the_headers_every_error_needs() {
+        let res = ErrorResponseBuilder::new(StatusCode::TOO_MANY_REQUESTS, "ref-1")
+            .header(header::RETRY_AFTER, HeaderValue::from_static("5"))
+            .text("Too many requests.\n".to_string());
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = res.headers();
+        for (name, value) in [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (ERROR_REFERENCE_HEADER, "ref-1"),
+            (header::RETRY_AFTER, "5"),
+            (header::CACHE_CONTROL, "no-store"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (header::X_FRAME_OPTIONS, "DENY"),
+        ] {
+            assert_eq!(headers.get(&name).unwrap(), value, "{}", name);
+        }
+        assert!(headers.get(header::CONTENT_SECURITY_POLICY).is_some());
+        // Outside a request there is no request id to echo
+        assert!(headers.get(REQUEST_ID_HEADER).is_none());
+    }
+}
@@ -0,0 +1,61 @@
+// =========================================================================
+// --- Server-Timing Header ---
+// =========================================================================
+
+// With `AppConfig::server_timing` on, handlers can report how long each
+// phase of a request took in a `Server-Timing` header, e.g.
+// `validate;dur=0.1, db;dur=12.3, render;dur=0.2` (milliseconds), which
+// browser dev tools show next to the request. Phase names are fixed
+// labels chosen in code, never derived from the request or the query.
+// Off by default: timings can help an attacker tell code paths apart.
+
+use actix_web::{
+    HttpResponse,
+    http::header::{HeaderName, HeaderValue},
+};
+use std::time::{Duration, Instant};
+
+use crate::config::AppConfig;
+
+pub const SERVER_TIMING_HEADER: HeaderName = HeaderName::from_static("server-timing");
+
+pub struct ServerTiming {
+    enabled: bool,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl ServerTiming {
+    // Start timing the first phase now.
+    pub fn start(config: &AppConfig) -> Self {
+        ServerTiming {
+            enabled: config.server_timing,
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    // End the current phase, recording it as `phase`, and start the next.
+    pub fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+
+    // Add the header to `response`, if enabled.
+    pub fn apply(self, mut response: HttpResponse) -> HttpResponse {
+        if !self.enabled || self.phases.is_empty() {
+            return response;
+        }
+        let value = self
+            .phases
+            .iter()
+            .map(|(phase, took)| format!("{};dur={:.1}", phase, took.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(SERVER_TIMING_HEADER, value);
+        }
+        response
+    }
+}
@@ -0,0 +1,73 @@
+// =========================================================================
+// --- Streaming Search Results ---
+// =========================================================================
+
+// Large result sets are sent as a chunked body, a few rows at a time,
+// instead of one big `String`.
+//
+// Once streaming starts the status code and headers are already sent, so an
+// error from the row source can't become a normal error response. Instead it
+// is logged like any other `AppError` (redacted, with the request id), and the
+// body is aborted. The client sees a truncated transfer rather than a page
+// that looks complete, and never any part of the error.
+
+use actix_web::{error, web::Bytes};
+use futures_util::{Stream, StreamExt, stream};
+use log::log;
+
+use crate::{AppError, html_escape, scrub_detail};
+
+// Rows rendered per chunk.
+const STREAM_CHUNK_ROWS: usize = 50;
+
+// Render the rows of one chunk, or log and abort on the first failed row.
+fn render_chunk(
+    rows: Vec<Result<String, AppError>>,
+    request_id: Option<&str>,
+) -> Result<Bytes, actix_web::Error> {
+    let mut html = String::new();
+    for row in rows {
+        match row {
+            Ok(row) => html.push_str(&format!("<li>{}</li>", html_escape(&row))),
+            Err(e) => {
+                log!(
+                    e.log_level(),
+                    request_id = request_id.unwrap_or("-"),
+                    variant = e.internal_code(),
+                    detail = scrub_detail(&e.to_string()).as_str();
+                    "SECURE (internal log): Search result stream aborted"
+                );
+                return Err(error::ErrorInternalServerError("stream aborted"));
+            }
+        }
+    }
+    Ok(Bytes::from(html))
+}
+
+// Stream an HTML result page for `product`: a header, the rows in chunks of
+// `STREAM_CHUNK_ROWS`, then a footer. Nothing is sent after an error.
+pub fn search_results(
+    product: &str,
+    rows: impl Stream<Item = Result<String, AppError>> + 'static,
+    request_id: Option<String>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> + 'static {
+    let header = format!(
+        "<h1>Search Result</h1><p>Products for: {}</p><ul>",
+        html_escape(product)
+    );
+    let body = rows
+        .chunks(STREAM_CHUNK_ROWS)
+        .map(move |chunk| render_chunk(chunk, request_id.as_deref()));
+
+    stream::once(async move { Ok(Bytes::from(header)) })
+        .chain(body)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"</ul>")) }))
+        .scan(false, |aborted, item| {
+            // Stop after passing the first error on to actix
+            if *aborted {
+                return std::future::ready(None);
+            }
+            *aborted = item.is_err();
+            std::future::ready(Some(item))
+        })
+}
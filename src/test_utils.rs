@@ -0,0 +1,97 @@
+// =========================================================================
+// --- Test Helpers (tests, and `--features test-utils`) ---
+// =========================================================================
+
+// Shared checks for handler tests, so every test asserts the crate's main
+// invariant the same way: no response body may contain a secret. Plus an
+// in-memory logger, for checking what did (and didn't) reach the log.
+
+use log::kv::{Error as KvError, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::sync::Once;
+
+use crate::KNOWN_SECRETS;
+
+// Return every known secret (`crate::KNOWN_SECRETS`) found in `body`.
+#[cfg_attr(not(test), allow(dead_code))] // Called from tests only.
+pub fn find_secrets(body: &str) -> Vec<&'static str> {
+    KNOWN_SECRETS
+        .iter()
+        .copied()
+        .filter(|secret| body.contains(secret))
+        .collect()
+}
+
+// Panic if `body` contains any known secret, naming the ones found.
+#[cfg_attr(not(test), allow(dead_code))] // Called from tests only.
+pub fn assert_no_secrets(body: &str) {
+    let found = find_secrets(body);
+    assert!(
+        found.is_empty(),
+        "response body leaks secrets {:?}: {}",
+        found,
+        body
+    );
+}
+
+// =========================================================================
+// --- Log Capture ---
+// =========================================================================
+
+// A logger that keeps every record in memory, formatted as
+// `LEVEL target: message key=value ...`, so tests can assert on what was
+// (and wasn't) logged. Records are kept per thread, so tests running in
+// parallel don't see each other's lines; with `#[actix_web::test]` the
+// handlers and `error_response()` run on the test's own thread.
+struct CaptureLogger;
+
+thread_local! {
+    static CAPTURED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// Appends a record's key-values as ` key=value`.
+struct KvLine<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for KvLine<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        self.0.push_str(&format!(" {}={}", key, value));
+        Ok(())
+    }
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut line = format!("{} {}: {}", record.level(), record.target(), record.args());
+        let _ = record.key_values().visit(&mut KvLine(&mut line));
+        CAPTURED.with(|captured| captured.borrow_mut().push(line));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+// Start capturing this thread's log records, dropping any captured so far.
+// `log` only allows one logger per process, so the first call installs it
+// (and later calls just clear); don't mix this with `init_logging`.
+#[cfg_attr(not(test), allow(dead_code))] // Called from tests only.
+pub fn install() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        if log::set_logger(&CAPTURE_LOGGER).is_ok() {
+            log::set_max_level(LevelFilter::Trace);
+        }
+    });
+    CAPTURED.with(|captured| captured.borrow_mut().clear());
+}
+
+// The lines logged on this thread since `install()`, oldest first.
+#[cfg_attr(not(test), allow(dead_code))] // Called from tests only.
+pub fn captured() -> Vec<String> {
+    CAPTURED.with(|captured| captured.borrow().clone())
+}